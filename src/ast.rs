@@ -2,6 +2,28 @@ use crate::token::Position;
 use std::fmt;
 use thiserror::Error;
 
+/// A line of a program worth keeping when it's printed again.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    /// A move and the comment after it on the same line, without the `#`.
+    Move(Move, Option<String>),
+    /// The text after the `#`.
+    Comment(String),
+    /// One or more empty lines.
+    Blank,
+}
+
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Statement::Move(move_, None) => write!(f, "{move_}"),
+            Statement::Move(move_, Some(text)) => write!(f, "{move_} #{text}"),
+            Statement::Comment(text) => write!(f, "#{text}"),
+            Statement::Blank => Ok(()),
+        }
+    }
+}
+
 #[derive(Debug, Error, Clone)]
 pub enum Move {
     #[error("cut {block_id} {point}")]
@@ -37,13 +59,136 @@ pub enum Move {
     },
 }
 
-#[derive(Debug, Clone, Copy)]
+impl Move {
+    /// Returns the blocks the move refers to, in source order.
+    pub fn block_ids(&self) -> Vec<&BlockId> {
+        match self {
+            Move::PCut { block_id, .. }
+            | Move::LCut { block_id, .. }
+            | Move::Color { block_id, .. } => vec![block_id],
+            Move::Swap {
+                block_id_1,
+                block_id_2,
+                ..
+            }
+            | Move::Merge {
+                block_id_1,
+                block_id_2,
+                ..
+            } => vec![block_id_1, block_id_2],
+        }
+    }
+
+    pub fn position(&self) -> &Position {
+        match self {
+            Move::PCut { position, .. }
+            | Move::LCut { position, .. }
+            | Move::Color { position, .. }
+            | Move::Swap { position, .. }
+            | Move::Merge { position, .. } => position,
+        }
+    }
+
+    /// Returns the same move with every block id passed through `f`.
+    pub fn map_block_ids(&self, f: impl Fn(&BlockId) -> BlockId) -> Move {
+        let mut move_ = self.clone();
+        match &mut move_ {
+            Move::PCut { block_id, .. }
+            | Move::LCut { block_id, .. }
+            | Move::Color { block_id, .. } => *block_id = f(block_id),
+            Move::Swap {
+                block_id_1,
+                block_id_2,
+                ..
+            }
+            | Move::Merge {
+                block_id_1,
+                block_id_2,
+                ..
+            } => {
+                *block_id_1 = f(block_id_1);
+                *block_id_2 = f(block_id_2);
+            }
+        }
+        move_
+    }
+}
+
+/// Moves are equal when they do the same thing, wherever they were written.
+impl PartialEq for Move {
+    fn eq(&self, other: &Self) -> bool {
+        use Move::*;
+        match (self, other) {
+            (
+                PCut {
+                    block_id, point, ..
+                },
+                PCut {
+                    block_id: block_id_,
+                    point: point_,
+                    ..
+                },
+            ) => block_id == block_id_ && point == point_,
+            (
+                LCut {
+                    block_id,
+                    orientation,
+                    line_no,
+                    ..
+                },
+                LCut {
+                    block_id: block_id_,
+                    orientation: orientation_,
+                    line_no: line_no_,
+                    ..
+                },
+            ) => block_id == block_id_ && orientation == orientation_ && line_no == line_no_,
+            (
+                Color {
+                    block_id, color, ..
+                },
+                Color {
+                    block_id: block_id_,
+                    color: color_,
+                    ..
+                },
+            ) => block_id == block_id_ && color == color_,
+            (
+                Swap {
+                    block_id_1,
+                    block_id_2,
+                    ..
+                },
+                Swap {
+                    block_id_1: block_id_1_,
+                    block_id_2: block_id_2_,
+                    ..
+                },
+            )
+            | (
+                Merge {
+                    block_id_1,
+                    block_id_2,
+                    ..
+                },
+                Merge {
+                    block_id_1: block_id_1_,
+                    block_id_2: block_id_2_,
+                    ..
+                },
+            ) => block_id_1 == block_id_1_ && block_id_2 == block_id_2_,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Color(pub u8, pub u8, pub u8, pub u8);
 
 impl fmt::Display for Color {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let Color(r, g, b, a) = self;
-        write!(f, "[{}, {}, {}, {}]", r, g, b, a)
+        write!(f, "[{},{},{},{}]", r, g, b, a)
     }
 }
 
@@ -55,7 +200,7 @@ pub struct Point {
 
 impl std::fmt::Display for Point {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "[x: {}, y:{}]", self.x, self.y)
+        write!(f, "[{},{}]", self.x, self.y)
     }
 }
 
@@ -124,6 +269,54 @@ impl BlockId {
             id,
         }
     }
+
+    /// Builds a block id from its ids, outermost first.
+    pub fn from_path(path: &[u64]) -> Option<BlockId> {
+        let (first, rest) = path.split_first()?;
+        let mut block_id = BlockId::new(*first);
+        for id in rest {
+            block_id = BlockId::new_with_prev(&block_id, *id);
+        }
+
+        Some(block_id)
+    }
+
+    /// Returns the ids making up the block id, outermost first.
+    pub fn path(&self) -> Vec<u64> {
+        let mut path = vec![self.id];
+        let mut curr = &self.prev;
+
+        while let Some(block_id) = curr {
+            path.push(block_id.id);
+            curr = &block_id.prev;
+        }
+
+        path.reverse();
+        path
+    }
+
+    /// Formats the block id without brackets, e.g. `0.3.1`.
+    pub fn path_string(&self) -> String {
+        self.path()
+            .iter()
+            .map(u64::to_string)
+            .collect::<Vec<_>>()
+            .join(".")
+    }
+}
+
+impl std::str::FromStr for BlockId {
+    type Err = std::num::ParseIntError;
+
+    /// Parses an unbracketed block id such as `0.3.1`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let path = s
+            .split('.')
+            .map(|id| id.trim().parse::<u64>())
+            .collect::<Result<Vec<u64>, _>>()?;
+
+        Ok(BlockId::from_path(&path).expect("split always yields an id"))
+    }
 }
 
 impl fmt::Display for BlockId {
@@ -140,7 +333,7 @@ impl fmt::Display for BlockId {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Orientation {
     Horizontal,
     Vertical,
@@ -1,72 +1,691 @@
-use crate::{error::ISLError, interpreter::Interpreter, parser::Parser, scanner::Scanner};
+use crate::{
+    ast::{BlockId, Color, Move},
+    config::InitialConfig,
+    cost::BaseCosts,
+    error::{ISLError, ISLResult},
+    image::Image,
+    interpreter::{Block, Interpreter, InterpreterError},
+    observer::{MoveEvent, Observer},
+    optimizer,
+    parser::Parser,
+    preview, printer,
+    scanner::Scanner,
+    trace::{TraceFormat, TraceWriter},
+};
 use colored::*;
 use std::{
-    borrow::Borrow,
-    io::{self, stdin, Write},
+    cell::RefCell,
+    collections::HashMap,
+    fs::File,
+    io::{stdin, stdout, BufWriter, Write},
+    rc::Rc,
 };
 
-pub fn run_repl(verbose: bool) {
-    let mut interpreter = Interpreter::new();
+/// The canvas a program starts painting on.
+#[derive(Debug, Clone)]
+pub struct CanvasConfig {
+    pub width: usize,
+    pub height: usize,
+    pub background: Color,
+    /// An initial configuration JSON, which takes precedence over the fields above.
+    pub initial: Option<String>,
+    /// The source PNG that image-backed initial blocks are cut from.
+    pub source_png: Option<String>,
+}
+
+impl Default for CanvasConfig {
+    fn default() -> Self {
+        CanvasConfig {
+            width: 400,
+            height: 400,
+            background: Color(255, 255, 255, 255),
+            initial: None,
+            source_png: None,
+        }
+    }
+}
+
+impl CanvasConfig {
+    pub fn interpreter(&self) -> ISLResult<Interpreter> {
+        self.load()?.interpreter()
+    }
+
+    /// Reads the initial configuration and source PNG, if any, from disk.
+    pub fn load(&self) -> ISLResult<LoadedCanvas> {
+        match &self.initial {
+            Some(path) => {
+                let config = InitialConfig::load(path)?;
+                let source = match &self.source_png {
+                    Some(source) => Some(Image::load_png(source)?),
+                    None => None,
+                };
+
+                Ok(LoadedCanvas::Initial { config, source })
+            }
+            None => Ok(LoadedCanvas::Blank {
+                width: self.width,
+                height: self.height,
+                background: self.background,
+            }),
+        }
+    }
+}
+
+/// A starting canvas with everything it needs from disk already read.
+#[derive(Debug, Clone)]
+pub enum LoadedCanvas {
+    Blank {
+        width: usize,
+        height: usize,
+        background: Color,
+    },
+    Initial {
+        config: InitialConfig,
+        source: Option<Image>,
+    },
+}
+
+impl LoadedCanvas {
+    pub fn interpreter(&self) -> ISLResult<Interpreter> {
+        match self {
+            LoadedCanvas::Blank {
+                width,
+                height,
+                background,
+            } => Ok(Interpreter::with_canvas(*width, *height, *background)),
+            LoadedCanvas::Initial { config, source } => {
+                Ok(Interpreter::from_config(config, source.as_ref())?)
+            }
+        }
+    }
+}
+
+/// Options for `run` on top of the program and its canvas.
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    /// A target PNG to score the final canvas against.
+    pub target: Option<String>,
+    /// Where to export the final blocks as an initial configuration.
+    pub export: Option<String>,
+    /// The cost models to score the program under, the lightning costs if empty.
+    pub cost_models: Vec<BaseCosts>,
+    /// Where to write a per-move trace.
+    pub trace: Option<String>,
+    /// The trace's format, picked from its `.csv` or `.jsonl` extension if unset.
+    pub trace_format: Option<TraceFormat>,
+    /// Draw the final canvas in the terminal, with block boundaries if `boundaries` is set.
+    pub preview: bool,
+    pub boundaries: bool,
+    /// The columns to draw the preview in, the terminal's width if unset.
+    pub preview_width: Option<usize>,
+    pub verbose: bool,
+}
+
+pub fn run_repl(canvas: CanvasConfig, verbose: bool) {
+    let mut session = match Session::new(canvas, verbose) {
+        Ok(session) => session,
+        Err(e) => {
+            println!("{e}");
+            return;
+        }
+    };
     println!("ICFP ISL Interpreter Version 1.0.0");
-    println!("Enter ':q' to quit.");
+    println!("Enter ':q' to quit, ':help' for commands.");
 
-    'a: loop {
+    loop {
         print!(" λ> ");
-        let mut input = String::new();
-        if let Err(e) = stdin().read_line(&mut input) {
+        if let Err(e) = stdout().flush() {
             println!("{}", ISLError::IO(e));
-            break 'a;
+            break;
+        }
+
+        let mut input = String::new();
+        match stdin().read_line(&mut input) {
+            Ok(0) => break,
+            Ok(_) => (),
+            Err(e) => {
+                println!("{}", ISLError::IO(e));
+                break;
+            }
         }
 
-        if input.contains(":q") || input.contains("exit") {
+        let input = input.trim();
+        if input == ":q" || input == ":quit" || input == "exit" {
             println!("{}", "Goodbye and thanks for all the fish".green());
-            break 'a;
+            break;
+        }
+
+        if input.starts_with(':') {
+            session.run_command(input);
+        } else if !input.is_empty() {
+            session.run_source(input);
+        }
+    }
+}
+
+const REPL_HELP: &str = "\
+:blocks              list the live blocks
+:block [id]          show one block
+:pixel x y           show the color of a pixel
+:show [blocks]       draw the canvas, outlining the blocks if asked
+:cost                show the running total cost
+:undo, :redo         take back or replay a move
+:checkpoint name     save the current state
+:restore name        go back to a checkpoint
+:checkpoints         list the checkpoints
+:reset               start over from a blank canvas
+:load file.isl       run a program
+:save file.isl       write the accepted moves as a program
+:q                   quit";
+
+/// The state of a REPL session: the interpreter and every accepted move, printed as ISL.
+struct Session {
+    canvas: CanvasConfig,
+    verbose: bool,
+    interpreter: Interpreter,
+    accepted: Vec<String>,
+    undone: Vec<String>,
+    checkpoints: HashMap<String, Vec<String>>,
+}
+
+impl Session {
+    fn new(canvas: CanvasConfig, verbose: bool) -> ISLResult<Session> {
+        let mut interpreter = canvas.interpreter()?;
+        if verbose {
+            interpreter.add_observer(Box::new(VerbosePrinter));
         }
+        interpreter.record_history(true);
+
+        Ok(Session {
+            canvas,
+            verbose,
+            interpreter,
+            accepted: Vec::new(),
+            undone: Vec::new(),
+            checkpoints: HashMap::new(),
+        })
+    }
 
-        let tokens = Scanner::scan_str(&input);
-        let moves = match Parser::parse_tokens(&tokens) {
+    /// Parses and runs `src` move by move, keeping every move that succeeds.
+    fn run_source(&mut self, src: &str) {
+        let moves = match Scanner::scan_str(src)
+            .map_err(ISLError::from)
+            .and_then(|tokens| Ok(Parser::parse_tokens(&tokens)?))
+        {
             Ok(yay) => yay,
             Err(e) => {
-                println!("{}", ISLError::Parser(e));
-                break 'a;
+                println!("{e}");
+                return;
             }
         };
-        match interpreter.interpret(&moves, verbose) {
-            Ok(cost) => {
-                println!("{} {}", "Cost:".blink().bold(), cost);
+
+        let mut cost = 0;
+        for move_ in &moves {
+            match self.interpreter.interpret(std::slice::from_ref(move_)) {
+                Ok(report) => {
+                    cost += report.total_cost;
+                    self.accepted.push(move_.to_string());
+                    self.undone.clear();
+                }
+                Err(e) => {
+                    println!("{}", ISLError::Interpreter(e));
+                    break;
+                }
             }
-            Err(e) => {
-                println!("{}", ISLError::Interpreter(e));
-                break 'a;
+        }
+        println!("{} {}", "Cost:".blink().bold(), cost);
+    }
+
+    fn run_command(&mut self, command: &str) {
+        let words = command.split_whitespace().collect::<Vec<_>>();
+
+        match words.as_slice() {
+            [":help"] => println!("{REPL_HELP}"),
+            [":blocks"] => {
+                let mut blocks = self.interpreter.blocks().iter().collect::<Vec<_>>();
+                blocks.sort_by_key(|(block_id, _)| block_id.path());
+                for (block_id, block) in blocks {
+                    println!(
+                        "{} {}",
+                        format!("{block_id}").bright_yellow(),
+                        block.shape()
+                    );
+                }
+            }
+            [":block", block_id] => {
+                let block_id = block_id.trim_start_matches('[').trim_end_matches(']');
+                let block = block_id
+                    .parse::<BlockId>()
+                    .ok()
+                    .and_then(|block_id| self.interpreter.blocks().get(&block_id));
+
+                match block {
+                    Some(block) => print_block(block),
+                    None => println!("{} [{}]", "No such block:".red(), block_id),
+                }
+            }
+            [":pixel", x, y] => match (x.parse::<u64>(), y.parse::<u64>()) {
+                (Ok(x), Ok(y)) => match self.interpreter.pixel(x, y) {
+                    Some(color) => println!("{color}"),
+                    None => println!("{} [{}, {}]", "Pixel out of bounds:".red(), x, y),
+                },
+                _ => println!("{}", "Usage: :pixel x y".red()),
+            },
+            [":show"] => print!("{}", preview(&self.interpreter, false, None)),
+            [":show", "blocks"] => print!("{}", preview(&self.interpreter, true, None)),
+            [":cost"] => println!(
+                "{} {}",
+                "Total Cost:".blink().bold(),
+                self.interpreter.total_cost()
+            ),
+            [":undo"] => match self.interpreter.undo() {
+                Some(move_) => {
+                    if let Some(line) = self.accepted.pop() {
+                        self.undone.push(line);
+                    }
+                    println!("{} {}", "Undid".yellow(), move_)
+                }
+                None => println!("{}", "Nothing to undo.".yellow()),
+            },
+            [":redo"] => match self.interpreter.redo() {
+                Ok(Some(record)) => {
+                    if let Some(line) = self.undone.pop() {
+                        self.accepted.push(line);
+                    }
+                    println!("{} {}", "Redid".yellow(), record.move_)
+                }
+                Ok(None) => println!("{}", "Nothing to redo.".yellow()),
+                Err(e) => println!("{}", ISLError::Interpreter(e)),
+            },
+            [":checkpoint", name] => {
+                self.interpreter.checkpoint(name);
+                self.checkpoints
+                    .insert(name.to_string(), self.accepted.clone());
+                println!("{} {}", "Saved checkpoint".green(), name);
+            }
+            [":restore", name] => match self.interpreter.restore(name) {
+                Ok(()) => {
+                    self.accepted = self.checkpoints.get(*name).cloned().unwrap_or_default();
+                    self.undone.clear();
+                    println!("{} {}", "Restored checkpoint".green(), name)
+                }
+                Err(e) => println!("{}", ISLError::Interpreter(e)),
+            },
+            [":checkpoints"] => println!("{}", self.interpreter.checkpoints().join(", ")),
+            [":reset"] => match Session::new(self.canvas.clone(), self.verbose) {
+                Ok(session) => {
+                    *self = session;
+                    println!("{}", "Started over.".green());
+                }
+                Err(e) => println!("{e}"),
+            },
+            [":load", file_name] => match std::fs::read_to_string(file_name) {
+                Ok(src) => self.run_source(&src),
+                Err(e) => println!("{}", ISLError::IO(e)),
+            },
+            [":save", file_name] => {
+                let mut program = self.accepted.join("\n");
+                program.push('\n');
+                match std::fs::write(file_name, program) {
+                    Ok(()) => println!(
+                        "{} {} moves to {}",
+                        "Saved".green(),
+                        self.accepted.len(),
+                        file_name
+                    ),
+                    Err(e) => println!("{}", ISLError::IO(e)),
+                }
+            }
+            _ => println!("{} {}", "Unknown command:".red(), command),
+        }
+    }
+}
+
+/// Draws the interpreter's canvas to fit in `columns`, or the terminal if not given.
+fn preview(interpreter: &Interpreter, boundaries: bool, columns: Option<usize>) -> String {
+    let shapes = boundaries.then(|| {
+        interpreter
+            .blocks()
+            .values()
+            .map(|block| block.shape().clone())
+            .collect::<Vec<_>>()
+    });
+
+    preview::render(
+        &interpreter.canvas(),
+        shapes.as_deref(),
+        columns.unwrap_or_else(preview::terminal_width),
+    )
+}
+
+fn print_block(block: &Block) {
+    let shape = block.shape();
+    println!(
+        "{} {} ({}x{}, {} pixels)",
+        "Shape:".bold(),
+        shape,
+        shape.width(),
+        shape.height(),
+        shape.size()
+    );
+
+    match block {
+        Block::Simple { fill, .. } => println!("{} {}", "Simple:".bold(), fill),
+        Block::Complex { children, .. } => {
+            println!("{} {} children", "Complex:".bold(), children.len());
+            for child in children {
+                println!("  {} {}", child.shape, child.fill);
             }
+        }
+    }
+}
+
+/// Prints every move with its cost as it runs, expensive moves in red.
+pub struct VerbosePrinter;
+
+impl Observer for VerbosePrinter {
+    fn after_move(&mut self, event: &MoveEvent) {
+        let move_ = format!("{}", event.record.move_).blue();
+        let cost = if event.record.cost < 2000 {
+            format!("{}", event.record.cost).green()
+        } else {
+            format!("{}", event.record.cost).red()
         };
+        let move_no = format!("Move {}", event.index).bright_yellow();
+        println!("[{move_no}]: {move_}\n{} {cost}", "Cost:".blink().bold())
+    }
+
+    fn on_error(&mut self, index: usize, move_: &Move, _error: &InterpreterError) {
+        let move_no = format!("Move {index}").bright_yellow();
+        println!("[{move_no}]: {}", format!("{move_}").red())
     }
 }
 
-pub fn run_file(file_name: String, verbose: bool) {
-    let mut interpreter = Interpreter::new();
-    let src = match std::fs::read_to_string(file_name) {
-        Ok(s) => s,
+fn read_program(file_name: &str) -> ISLResult<Vec<Move>> {
+    let src = std::fs::read_to_string(file_name)?;
+    let tokens = Scanner::scan_str(&src)?;
+    Ok(Parser::parse_tokens(&tokens)?)
+}
+
+pub fn run_file(file_name: String, canvas: CanvasConfig, options: RunOptions) {
+    let moves = match read_program(&file_name) {
+        Ok(moves) => moves,
         Err(e) => {
-            println!("{}", ISLError::IO(e));
+            println!("{e}");
             return;
         }
     };
 
-    let tokens = Scanner::scan_str(&src);
-    let moves = match Parser::parse_tokens(&tokens) {
+    let cost_models = if options.cost_models.is_empty() {
+        vec![BaseCosts::default()]
+    } else {
+        options.cost_models
+    };
+
+    let trace = match &options.trace {
+        Some(path) => {
+            let Some(format) = options
+                .trace_format
+                .or_else(|| TraceFormat::from_path(path))
+            else {
+                println!(
+                    "{} Can't tell the format of '{path}' from its extension, pass '--trace-format csv' or 'jsonl'.",
+                    "Trace Error:".red().bold()
+                );
+                return;
+            };
+
+            match File::create(path) {
+                Ok(file) => Some(Rc::new(RefCell::new(TraceWriter::new(
+                    BufWriter::new(file),
+                    format,
+                )))),
+                Err(e) => {
+                    println!("{}", ISLError::IO(e));
+                    return;
+                }
+            }
+        }
+        None => None,
+    };
+
+    // Every cost model replays the program, the canvas they leave behind is the same.
+    let mut interpreter = None;
+    let mut costs = Vec::new();
+    for (i, cost_model) in cost_models.into_iter().enumerate() {
+        let mut interpreter_ = match canvas.interpreter() {
+            Ok(interpreter_) => interpreter_,
+            Err(e) => {
+                println!("{e}");
+                return;
+            }
+        };
+        let name = cost_model.name;
+        interpreter_.set_cost_model(Box::new(cost_model));
+        if i == 0 {
+            if options.verbose {
+                interpreter_.add_observer(Box::new(VerbosePrinter));
+            }
+            if let Some(trace) = &trace {
+                interpreter_.add_observer(Box::new(trace.clone()));
+            }
+        }
+
+        match interpreter_.interpret(&moves) {
+            Ok(report) => costs.push((name, report.total_cost)),
+            Err(e) => {
+                println!("{}", ISLError::Interpreter(e));
+                return;
+            }
+        };
+        interpreter = Some(interpreter_);
+    }
+    let interpreter = interpreter.expect("at least one cost model is used");
+
+    let label = |title: &str, name: &str| {
+        if costs.len() == 1 {
+            format!("{title}:")
+        } else {
+            format!("{title} ({name}):")
+        }
+    };
+
+    for (name, cost) in &costs {
+        println!("{} {}", label("Total Cost", name).blink().bold(), cost);
+    }
+
+    if options.preview {
+        print!(
+            "{}",
+            preview(&interpreter, options.boundaries, options.preview_width)
+        );
+    }
+
+    if let (Some(trace), Some(path)) = (trace, &options.trace) {
+        if let Err(e) = trace.borrow_mut().finish() {
+            println!("{}", ISLError::IO(e));
+            return;
+        }
+        println!("{} {}", "Wrote trace to".green(), path);
+    }
+
+    if let Some(export) = options.export {
+        if let Err(e) = interpreter
+            .to_config()
+            .and_then(|config| config.save(&export))
+        {
+            println!("{}", ISLError::Config(e));
+            return;
+        }
+        println!("{} {}", "Exported blocks to".green(), export);
+    }
+
+    if let Some(target) = options.target {
+        let similarity = match Image::load_png(target)
+            .and_then(|target| target.similarity(&interpreter.canvas()))
+        {
+            Ok(similarity) => similarity,
+            Err(e) => {
+                println!("{}", ISLError::Image(e));
+                return;
+            }
+        };
+        println!("{} {}", "Similarity:".bold(), similarity);
+        for (name, cost) in &costs {
+            println!(
+                "{} {}",
+                label("Score", name).blink().bold(),
+                cost + similarity
+            );
+        }
+    }
+}
+
+/// Runs every optimization pass over a program, writing the result to `output` or stdout.
+pub fn optimize_file(
+    file_name: String,
+    canvas: CanvasConfig,
+    cost_model: BaseCosts,
+    output: Option<String>,
+) {
+    // Every pass runs the program several times, so the canvas is only read from disk once.
+    let setup = canvas.load().and_then(|loaded| {
+        // Building it up front means the passes can't hit a bad configuration.
+        loaded.interpreter()?;
+        Ok((loaded, read_program(&file_name)?))
+    });
+    let (loaded, mut moves) = match setup {
         Ok(yay) => yay,
         Err(e) => {
-            println!("{}", ISLError::Parser(e));
+            println!("{e}");
             return;
         }
     };
-    match interpreter.interpret(&moves, verbose) {
-        Ok(cost) => {
-            println!("{} {}", "Total Cost:".blink().bold(), cost);
+
+    let start = || {
+        let mut interpreter = loaded
+            .interpreter()
+            .expect("the canvas was built once already");
+        interpreter.set_cost_model(Box::new(cost_model.clone()));
+        interpreter
+    };
+
+    // The program goes to stdout without an output file, so the summary goes to stderr.
+    let mut summary = Vec::new();
+    for (name, pass) in optimizer::PASSES {
+        match pass(&moves, &start) {
+            Ok(report) => {
+                summary.push(format!(
+                    "{} {} -> {} (saved {})",
+                    format!("{name}:").bold(),
+                    report.cost_before,
+                    report.cost_after,
+                    report.saved()
+                ));
+                moves = report.moves;
+            }
+            Err(e) => {
+                println!("{}", ISLError::Interpreter(e));
+                return;
+            }
+        }
+    }
+
+    let program = printer::print_moves(&moves);
+    match output {
+        Some(output) => {
+            if let Err(e) = std::fs::write(&output, program) {
+                println!("{}", ISLError::IO(e));
+                return;
+            }
+            summary.iter().for_each(|line| println!("{line}"));
+            println!("{} {}", "Wrote".green(), output);
+        }
+        None => {
+            print!("{program}");
+            summary.iter().for_each(|line| eprintln!("{line}"));
         }
+    }
+}
+
+/// Prints a program in canonical ISL, or rewrites the file in place with `write`.
+/// With `check` nothing is written and it returns whether the file is already canonical.
+pub fn format_file(file_name: String, write: bool, check: bool) -> bool {
+    let formatted = match std::fs::read_to_string(&file_name)
+        .map_err(ISLError::from)
+        .and_then(|src| Ok((printer::format_source(&src)?, src)))
+    {
+        Ok((formatted, src)) if check => {
+            if formatted != src {
+                println!("{} {}", "Not formatted:".red().bold(), file_name);
+            }
+            return formatted == src;
+        }
+        Ok((formatted, _)) => formatted,
         Err(e) => {
-            println!("{}", ISLError::Interpreter(e));
+            println!("{e}");
+            return false;
         }
     };
+
+    if write {
+        if let Err(e) = std::fs::write(&file_name, formatted) {
+            println!("{}", ISLError::IO(e));
+            return false;
+        }
+        println!("{} {}", "Formatted".green(), file_name);
+    } else {
+        print!("{formatted}");
+    }
+    true
+}
+
+/// Validates a program's block geometry, printing every error. Returns whether it's valid.
+pub fn check_file(file_name: String, canvas: CanvasConfig) -> bool {
+    let (interpreter, moves) = match canvas
+        .interpreter()
+        .and_then(|interpreter| Ok((interpreter, read_program(&file_name)?)))
+    {
+        Ok(yay) => yay,
+        Err(e) => {
+            println!("{e}");
+            return false;
+        }
+    };
+
+    let errors = interpreter.check(&moves);
+    let count = errors.len();
+    for e in errors {
+        println!("{}", ISLError::Interpreter(e));
+    }
+
+    if count == 0 {
+        println!("{} {} moves", "No errors in".green(), moves.len());
+        true
+    } else {
+        println!("{} {}", "Errors:".red().bold(), count);
+        false
+    }
+}
+
+pub fn render_file(file_name: String, canvas: CanvasConfig, output: String, scale: usize) {
+    let (mut interpreter, moves) = match canvas
+        .interpreter()
+        .and_then(|interpreter| Ok((interpreter, read_program(&file_name)?)))
+    {
+        Ok(yay) => yay,
+        Err(e) => {
+            println!("{e}");
+            return;
+        }
+    };
+    if let Err(e) = interpreter.interpret(&moves) {
+        println!("{}", ISLError::Interpreter(e));
+        return;
+    }
+
+    match interpreter.canvas().save_png(&output, scale) {
+        Ok(()) => println!("{} {}", "Rendered to".green(), output),
+        Err(e) => println!("{}", ISLError::Image(e)),
+    }
 }
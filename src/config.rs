@@ -0,0 +1,72 @@
+use crate::ast::BlockId;
+
+use serde::{Deserialize, Serialize};
+use std::{fs::File, io::BufReader, path::Path};
+use thiserror::Error;
+
+/// The contest's initial configuration, a canvas already split into colored blocks.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InitialConfig {
+    pub width: usize,
+    pub height: usize,
+    pub blocks: Vec<InitialBlock>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InitialBlock {
+    pub block_id: String,
+    pub bottom_left: [u64; 2],
+    pub top_right: [u64; 2],
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<[u8; 4]>,
+    /// Where the block's bottom-left corner lies in the source PNG, instead of a color.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub png_bottom_left_point: Option<[u64; 2]>,
+}
+
+impl InitialConfig {
+    pub fn load<P: AsRef<Path>>(path: P) -> ConfigResult<InitialConfig> {
+        let file = File::open(path)?;
+        Ok(serde_json::from_reader(BufReader::new(file))?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> ConfigResult<()> {
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+
+    pub fn from_json(json: &str) -> ConfigResult<InitialConfig> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub fn to_json(&self) -> ConfigResult<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("Couldn't read initial configuration, {0}.")]
+    IO(#[from] std::io::Error),
+    #[error("Invalid initial configuration JSON, {0}.")]
+    Json(#[from] serde_json::Error),
+    #[error("Invalid block id '{0}' in initial configuration.")]
+    InvalidBlockId(String),
+    #[error("Block '{0}' is empty or lies outside the {1}x{2} canvas.")]
+    OutOfCanvas(String, usize, usize),
+    #[error("Block '{0}' has neither a color nor a pngBottomLeftPoint.")]
+    NoFill(String),
+    #[error("Block '{0}' is backed by the source PNG, but none was supplied.")]
+    MissingSource(String),
+    #[error("Block '{0}' reaches outside the source PNG.")]
+    OutOfSource(String),
+    #[error("Block id '{0}' appears more than once.")]
+    DuplicateBlock(String),
+    #[error("Block {0} is complex and can't be exported with a single fill.")]
+    ComplexBlock(BlockId),
+}
+
+pub type ConfigResult<T> = Result<T, ConfigError>;
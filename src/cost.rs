@@ -0,0 +1,120 @@
+use crate::ast::Move;
+
+/// The kinds of moves that are priced separately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MoveKind {
+    LineCut,
+    PointCut,
+    Color,
+    Swap,
+    Merge,
+}
+
+impl MoveKind {
+    pub fn of(move_: &Move) -> MoveKind {
+        match move_ {
+            Move::LCut { .. } => MoveKind::LineCut,
+            Move::PCut { .. } => MoveKind::PointCut,
+            Move::Color { .. } => MoveKind::Color,
+            Move::Swap { .. } => MoveKind::Swap,
+            Move::Merge { .. } => MoveKind::Merge,
+        }
+    }
+}
+
+/// Prices moves, the contest charges `base * canvas_size / block_size` rounded to the nearest integer.
+pub trait CostModel {
+    fn name(&self) -> &str;
+
+    fn base_cost(&self, kind: MoveKind) -> u64;
+
+    fn cost(&self, kind: MoveKind, canvas_size: u64, block_size: u64) -> u64 {
+        let cost = self.base_cost(kind) as f64 * canvas_size as f64 / block_size as f64;
+        cost.round() as u64
+    }
+}
+
+/// A cost model with a fixed base cost per move kind.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BaseCosts {
+    pub name: &'static str,
+    pub line_cut: u64,
+    pub point_cut: u64,
+    pub color: u64,
+    pub swap: u64,
+    pub merge: u64,
+}
+
+impl BaseCosts {
+    /// The costs the contest started with.
+    pub const LIGHTNING: BaseCosts = BaseCosts {
+        name: "lightning",
+        line_cut: 7,
+        point_cut: 10,
+        color: 5,
+        swap: 3,
+        merge: 1,
+    };
+
+    /// The cheaper cuts introduced partway through the full contest.
+    pub const FULL_CONTEST: BaseCosts = BaseCosts {
+        name: "full",
+        line_cut: 2,
+        point_cut: 3,
+        color: 5,
+        swap: 3,
+        merge: 1,
+    };
+
+    pub const PRESETS: [BaseCosts; 2] = [BaseCosts::LIGHTNING, BaseCosts::FULL_CONTEST];
+
+    /// Looks up a built-in preset by name.
+    pub fn preset(name: &str) -> Option<BaseCosts> {
+        BaseCosts::PRESETS
+            .into_iter()
+            .find(|preset| preset.name == name)
+    }
+}
+
+impl Default for BaseCosts {
+    fn default() -> Self {
+        BaseCosts::LIGHTNING
+    }
+}
+
+impl CostModel for BaseCosts {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn base_cost(&self, kind: MoveKind) -> u64 {
+        match kind {
+            MoveKind::LineCut => self.line_cut,
+            MoveKind::PointCut => self.point_cut,
+            MoveKind::Color => self.color,
+            MoveKind::Swap => self.swap,
+            MoveKind::Merge => self.merge,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn costs_are_rounded_like_the_spec() {
+        let costs = BaseCosts::LIGHTNING;
+        // 5 * 160000 / 17500 = 45.71...
+        assert_eq!(costs.cost(MoveKind::Color, 160_000, 17_500), 46);
+        // 7 * 160000 / 150000 = 7.46...
+        assert_eq!(costs.cost(MoveKind::LineCut, 160_000, 150_000), 7);
+    }
+
+    #[test]
+    fn presets_are_found_by_name() {
+        assert_eq!(BaseCosts::preset("full"), Some(BaseCosts::FULL_CONTEST));
+        assert_eq!(BaseCosts::preset("lightning"), Some(BaseCosts::LIGHTNING));
+        assert_eq!(BaseCosts::preset("nope"), None);
+    }
+}
@@ -1,44 +1,67 @@
-use crate::{interpreter::InterpreterError, parser::ParserError};
+use crate::{
+    config::ConfigError, image::ImageError, interpreter::InterpreterError, parser::ParserError,
+    scanner::ScannerError,
+};
 
 use colored::*;
+use miette::{Diagnostic, GraphicalReportHandler};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
 pub enum ISLError {
-    Parser(#[from] ParserError),
+    /// Every lexical error in the program.
+    Scanner(Vec<ScannerError>),
+    /// Every syntax error in the program.
+    Parser(Vec<ParserError>),
     Interpreter(#[from] InterpreterError),
+    Image(#[from] ImageError),
+    Config(#[from] ConfigError),
     IO(#[from] std::io::Error),
 }
 
+impl From<Vec<ScannerError>> for ISLError {
+    fn from(errors: Vec<ScannerError>) -> Self {
+        ISLError::Scanner(errors)
+    }
+}
+
+impl From<Vec<ParserError>> for ISLError {
+    fn from(errors: Vec<ParserError>) -> Self {
+        ISLError::Parser(errors)
+    }
+}
+
+impl ISLError {
+    /// The errors that point into the program.
+    fn diagnostics(&self) -> Vec<&dyn Diagnostic> {
+        match self {
+            ISLError::Scanner(errors) => errors.iter().map(|e| e as &dyn Diagnostic).collect(),
+            ISLError::Parser(errors) => errors.iter().map(|e| e as &dyn Diagnostic).collect(),
+            ISLError::Interpreter(err) => vec![err],
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Errors in the program are rendered as miette reports, underlining the
+/// source they point at.
 impl std::fmt::Display for ISLError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         use ISLError::*;
-        match self {
-            Parser(err) => {
-                write!(
-                    f,
-                    "{} {}",
-                    "Parser Error:".red().bold(),
-                    format!("{err}").white()
-                )
+        let (label, err): (&str, &dyn std::error::Error) = match self {
+            Image(err) => ("Image Error:", err),
+            Config(err) => ("Config Error:", err),
+            IO(err) => ("IO Error:", err),
+            Scanner(_) | Parser(_) | Interpreter(_) => {
+                let handler = GraphicalReportHandler::new();
+                for diagnostic in self.diagnostics() {
+                    handler.render_report(f, diagnostic)?;
+                }
+                return Ok(());
             }
-            Interpreter(err) => {
-                write!(
-                    f,
-                    "{} {}",
-                    "Interpreter Error:".red().bold(),
-                    format!("{err}").white()
-                )
-            }
-            IO(err) => {
-                write!(
-                    f,
-                    "{} {}",
-                    "Parser Error:".red().bold(),
-                    format!("{err}").white()
-                )
-            }
-        }
+        };
+
+        write!(f, "{} {}", label.red().bold(), format!("{err}").white())
     }
 }
 
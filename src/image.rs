@@ -0,0 +1,176 @@
+use crate::ast::Color;
+
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::Path,
+};
+use thiserror::Error;
+
+/// Weight applied to the summed pixel distance by the contest's similarity formula.
+const SIMILARITY_ALPHA: f64 = 0.005;
+
+/// A grid of pixels laid out like `Interpreter.pixels`, bottom row first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Color>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize, pixels: Vec<Color>) -> Image {
+        Image {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Loads a PNG, flipping its top-left origin to ISL's bottom-left origin.
+    pub fn load_png<P: AsRef<Path>>(path: P) -> ImageResult<Image> {
+        let file = File::open(path)?;
+        let mut decoder = png::Decoder::new(BufReader::new(file));
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+
+        let mut reader = decoder.read_info()?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf)?;
+
+        let width = info.width as usize;
+        let height = info.height as usize;
+        let channels = info.color_type.samples();
+        let row_len = info.line_size;
+
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in (0..height).rev() {
+            let row = &buf[y * row_len..(y + 1) * row_len];
+            for px in row.chunks_exact(channels).take(width) {
+                let color = match px {
+                    [l] => Color(*l, *l, *l, 255),
+                    [l, a] => Color(*l, *l, *l, *a),
+                    [r, g, b] => Color(*r, *g, *b, 255),
+                    [r, g, b, a] => Color(*r, *g, *b, *a),
+                    _ => return Err(ImageError::UnsupportedFormat(info.color_type)),
+                };
+                pixels.push(color);
+            }
+        }
+
+        Ok(Image::new(width, height, pixels))
+    }
+
+    /// Writes the image as an RGBA PNG, upscaling each pixel to a `scale` x `scale` square.
+    pub fn save_png<P: AsRef<Path>>(&self, path: P, scale: usize) -> ImageResult<()> {
+        let scale = scale.max(1);
+        let out_width = self.width * scale;
+        let out_height = self.height * scale;
+
+        let file = File::create(path)?;
+        let mut encoder =
+            png::Encoder::new(BufWriter::new(file), out_width as u32, out_height as u32);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+
+        let mut data = Vec::with_capacity(out_width * out_height * 4);
+        for y in (0..self.height).rev() {
+            let mut row = Vec::with_capacity(out_width * 4);
+            for Color(r, g, b, a) in &self.pixels[y * self.width..(y + 1) * self.width] {
+                for _ in 0..scale {
+                    row.extend_from_slice(&[*r, *g, *b, *a]);
+                }
+            }
+            for _ in 0..scale {
+                data.extend_from_slice(&row);
+            }
+        }
+
+        writer.write_image_data(&data)?;
+        Ok(())
+    }
+
+    /// Computes the contest similarity between two images of the same size.
+    pub fn similarity(&self, other: &Image) -> ImageResult<u64> {
+        if (self.width, self.height) != (other.width, other.height) {
+            return Err(ImageError::SizeMismatch(
+                (self.width, self.height),
+                (other.width, other.height),
+            ));
+        }
+
+        Ok(similarity(&self.pixels, &other.pixels))
+    }
+}
+
+/// Sums the per-pixel RGBA euclidean distance and scales it by the contest's weight.
+pub fn similarity(lhs: &[Color], rhs: &[Color]) -> u64 {
+    let diff: f64 = lhs
+        .iter()
+        .zip(rhs.iter())
+        .map(|(Color(r1, g1, b1, a1), Color(r2, g2, b2, a2))| {
+            let channels = [(r1, r2), (g1, g2), (b1, b2), (a1, a2)];
+            channels
+                .iter()
+                .map(|(c1, c2)| {
+                    let d = **c1 as f64 - **c2 as f64;
+                    d * d
+                })
+                .sum::<f64>()
+                .sqrt()
+        })
+        .sum();
+
+    (diff * SIMILARITY_ALPHA).round() as u64
+}
+
+#[derive(Debug, Error)]
+pub enum ImageError {
+    #[error("Couldn't read image, {0}.")]
+    IO(#[from] std::io::Error),
+    #[error("Couldn't decode PNG, {0}.")]
+    Decoding(#[from] png::DecodingError),
+    #[error("Couldn't encode PNG, {0}.")]
+    Encoding(#[from] png::EncodingError),
+    #[error("Unsupported PNG color type {0:?}.")]
+    UnsupportedFormat(png::ColorType),
+    #[error("Image sizes don't match, {0:?} vs {1:?}.")]
+    SizeMismatch((usize, usize), (usize, usize)),
+}
+
+pub type ImageResult<T> = Result<T, ImageError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identical_images_are_fully_similar() {
+        let pixels = vec![Color(12, 34, 56, 255); 16];
+        assert_eq!(similarity(&pixels, &pixels), 0);
+    }
+
+    #[test]
+    fn similarity_matches_contest_formula() {
+        // Each pixel is sqrt(3 * 255^2) ~ 441.67 away, 400 * 441.67 * 0.005 ~ 883.
+        let black = vec![Color(0, 0, 0, 255); 400];
+        let white = vec![Color(255, 255, 255, 255); 400];
+        assert_eq!(similarity(&black, &white), 883);
+    }
+
+    #[test]
+    fn png_round_trip_keeps_bottom_left_origin() {
+        let red = Color(255, 0, 0, 255);
+        let blue = Color(0, 0, 255, 128);
+        let image = Image::new(2, 2, vec![red, red, blue, blue]);
+
+        let path = std::env::temp_dir().join("icfp_isl_round_trip.png");
+        image.save_png(&path, 3).unwrap();
+        let loaded = Image::load_png(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!((loaded.width, loaded.height), (6, 6));
+        assert_eq!(loaded.pixels[0], red);
+        assert_eq!(loaded.pixels[6 * 6 - 1], blue);
+    }
+}
@@ -1,18 +1,60 @@
 use crate::{
     ast::{within, BlockId, Color, Move, Orientation, Point},
+    config::{ConfigError, ConfigResult, InitialBlock, InitialConfig},
+    cost::{BaseCosts, CostModel, MoveKind},
+    image::Image,
+    observer::{MoveEvent, Observer},
+    report::{MoveRecord, RunReport},
     token::Position,
 };
 
-use colored::*;
-use std::collections::HashMap;
+use miette::{Diagnostic, LabeledSpan, SourceCode};
+use std::collections::{HashMap, HashSet};
 use thiserror::Error;
 
 pub struct Interpreter {
-    blocks: HashMap<BlockId, BlockData>,
+    blocks: HashMap<BlockId, Block>,
     counter: u64,
     width: usize,
     height: usize,
     pixels: Vec<Color>,
+    cost_model: Box<dyn CostModel>,
+    observers: Vec<Box<dyn Observer>>,
+    total_cost: u64,
+    /// The number of moves executed so far, across calls to `interpret`.
+    moves_run: usize,
+    history: Option<History>,
+    /// Whether moves paint the canvas, `check` only simulates the blocks.
+    paint: bool,
+}
+
+/// Undo and redo stacks plus named checkpoints, only kept once enabled.
+#[derive(Debug, Default)]
+struct History {
+    undo: Vec<UndoEntry>,
+    redo: Vec<Move>,
+    checkpoints: HashMap<String, Snapshot>,
+}
+
+/// Just enough to take a move back: the blocks it touched and the pixels it painted over.
+#[derive(Debug)]
+struct UndoEntry {
+    move_: Move,
+    consumed: Vec<(BlockId, Block)>,
+    produced: Vec<BlockId>,
+    pixels: Vec<(BlockData, Vec<Color>)>,
+    counter: u64,
+    cost: u64,
+}
+
+/// A full copy of the interpreter's state.
+#[derive(Debug, Clone)]
+struct Snapshot {
+    blocks: HashMap<BlockId, Block>,
+    counter: u64,
+    pixels: Vec<Color>,
+    total_cost: u64,
+    moves_run: usize,
 }
 
 impl Default for Interpreter {
@@ -22,155 +64,551 @@ impl Default for Interpreter {
 }
 
 impl Interpreter {
+    /// Creates an interpreter for the contest's default 400x400 white canvas.
     pub fn new() -> Interpreter {
-        let width: u64 = 100;
-        let heigth: u64 = 100;
+        Interpreter::with_canvas(400, 400, Color(255, 255, 255, 255))
+    }
 
+    /// Creates an interpreter with a single block covering a `width` x `height` canvas.
+    pub fn with_canvas(width: usize, height: usize, background: Color) -> Interpreter {
         let mut blocks = HashMap::new();
         blocks.insert(
             BlockId::new(0),
-            BlockData {
-                tl: Point::new(0, heigth),
-                tr: Point::new(width, heigth),
-                bl: Point::new(0, 0),
-                br: Point::new(width, 0),
+            Block::Simple {
+                shape: BlockData::from_corners(
+                    Point::new(0, 0),
+                    Point::new(width as u64, height as u64),
+                ),
+                fill: Fill::Color(background),
             },
         );
 
-        let width = width as usize;
-        let height = heigth as usize;
-        let pixel_count = width * height;
-
-        let mut pixels = Vec::with_capacity(pixel_count);
-        for _ in 0..pixel_count {
-            pixels.push(Color(255, 255, 255, 1))
-        }
-
         Interpreter {
+            counter: blocks.len() as u64,
             blocks,
-            counter: 0,
             width,
             height,
-            pixels,
+            pixels: vec![background; width * height],
+            cost_model: Box::new(BaseCosts::default()),
+            observers: Vec::new(),
+            total_cost: 0,
+            moves_run: 0,
+            history: None,
+            paint: true,
         }
     }
 
-    pub fn interpret(&mut self, moves: &[Move], verbose: bool) -> Result<u64, InterpreterError> {
-        let mut sum = 0;
-        let canvas_size = (self.height * self.width) as u64;
+    /// Creates an interpreter from the contest's initial configuration, painting each block.
+    /// Blocks with a `pngBottomLeftPoint` copy their pixels from `source`.
+    pub fn from_config(
+        config: &InitialConfig,
+        source: Option<&Image>,
+    ) -> ConfigResult<Interpreter> {
+        let mut interpreter = Interpreter {
+            blocks: HashMap::new(),
+            counter: config.blocks.len() as u64,
+            width: config.width,
+            height: config.height,
+            pixels: vec![Color(0, 0, 0, 0); config.width * config.height],
+            cost_model: Box::new(BaseCosts::default()),
+            observers: Vec::new(),
+            total_cost: 0,
+            moves_run: 0,
+            history: None,
+            paint: true,
+        };
 
-        for (i, move_) in moves.iter().enumerate() {
-            match self.execute(move_.clone()) {
-                Ok((base_cost, block_size)) => {
-                    let cost = base_cost * canvas_size / block_size;
-                    sum += cost;
-                    if verbose {
-                        let move_ = format!("{move_}").blue();
-                        let cost = if cost < 2000 {
-                            format!("{cost}").green()
-                        } else {
-                            format!("{cost}").red()
-                        };
-                        let move_no = format!("Move {i}").bright_yellow();
-                        println!("[{move_no}]: {move_}\n{} {cost}", "Cost:".blink().bold())
+        for initial in &config.blocks {
+            let block_id = initial
+                .block_id
+                .parse::<BlockId>()
+                .map_err(|_| ConfigError::InvalidBlockId(initial.block_id.clone()))?;
+
+            let [x0, y0] = initial.bottom_left;
+            let [x1, y1] = initial.top_right;
+            if !(x0 < x1 && y0 < y1 && x1 <= config.width as u64 && y1 <= config.height as u64) {
+                return Err(ConfigError::OutOfCanvas(
+                    initial.block_id.clone(),
+                    config.width,
+                    config.height,
+                ));
+            }
+
+            let shape = BlockData::from_corners(Point::new(x0, y0), Point::new(x1, y1));
+            let fill = match (initial.color, initial.png_bottom_left_point, source) {
+                (Some([r, g, b, a]), _, _) => {
+                    let color = Color(r, g, b, a);
+                    interpreter.color_block(shape.clone(), color);
+                    Fill::Color(color)
+                }
+                (None, Some([px, py]), Some(source)) => {
+                    let offset = Point::new(px, py);
+                    if px + shape.width() > source.width as u64
+                        || py + shape.height() > source.height as u64
+                    {
+                        return Err(ConfigError::OutOfSource(initial.block_id.clone()));
+                    }
+                    interpreter.copy_from_source(&shape, offset, source);
+                    Fill::Image { offset }
+                }
+                (None, Some(_), None) => {
+                    return Err(ConfigError::MissingSource(initial.block_id.clone()))
+                }
+                (None, None, _) => return Err(ConfigError::NoFill(initial.block_id.clone())),
+            };
+
+            if interpreter
+                .blocks
+                .insert(block_id, Block::Simple { shape, fill })
+                .is_some()
+            {
+                return Err(ConfigError::DuplicateBlock(initial.block_id.clone()));
+            }
+        }
+
+        Ok(interpreter)
+    }
+
+    /// Replaces the cost model, the lightning round's costs are used by default.
+    pub fn set_cost_model(&mut self, cost_model: Box<dyn CostModel>) {
+        self.cost_model = cost_model;
+    }
+
+    pub fn cost_model(&self) -> &dyn CostModel {
+        self.cost_model.as_ref()
+    }
+
+    /// Registers an observer that's notified around every move.
+    pub fn add_observer(&mut self, observer: Box<dyn Observer>) {
+        self.observers.push(observer);
+    }
+
+    /// Runs `moves` in order, stopping at the first move that fails.
+    pub fn interpret(&mut self, moves: &[Move]) -> InterpreterResult<RunReport> {
+        if let Some(history) = &mut self.history {
+            history.redo.clear();
+        }
+
+        let mut records = Vec::with_capacity(moves.len());
+        let mut total_cost = 0;
+
+        for move_ in moves {
+            let record = self.step(move_)?;
+            total_cost += record.cost;
+            records.push(record);
+        }
+
+        Ok(RunReport {
+            moves: records,
+            total_cost,
+            canvas: self.canvas(),
+            blocks: self.blocks.clone(),
+        })
+    }
+
+    /// Checks every move against the block geometry without painting anything.
+    ///
+    /// Unlike `interpret` this carries on past a failed move, the blocks it
+    /// would have touched are forgotten so moves using them aren't reported too.
+    pub fn check(mut self, moves: &[Move]) -> Vec<InterpreterError> {
+        self.paint = false;
+        let mut unknown = HashSet::new();
+        let mut errors = Vec::new();
+
+        for move_ in moves {
+            let is_unknown = |block_id: &BlockId| {
+                let mut block_id = Some(block_id);
+                while let Some(id) = block_id {
+                    if unknown.contains(id) {
+                        return true;
                     }
+                    block_id = id.prev.as_deref();
                 }
-                Err(e) => return Err(e),
+                false
+            };
+
+            if move_.block_ids().into_iter().any(is_unknown) {
+                self.forget(move_, &mut unknown);
+                continue;
+            }
+
+            if let Err(e) = self.execute(move_.clone()) {
+                self.forget_failed(move_, &e, &mut unknown);
+                errors.push(e);
             }
         }
 
-        Ok(sum)
+        errors
     }
-    fn execute(&mut self, m: Move) -> InterpreterResult<(u64, u64)> {
-        let res = match m {
+
+    /// Drops the blocks a skipped move refers to, as their shapes depend on
+    /// unknown blocks, and the block a merge would have made.
+    fn forget(&mut self, move_: &Move, unknown: &mut HashSet<BlockId>) {
+        for block_id in move_.block_ids() {
+            self.blocks.remove(block_id);
+            unknown.insert(block_id.clone());
+        }
+
+        if let Move::Merge { .. } = move_ {
+            unknown.insert(BlockId::new(self.counter));
+            self.counter += 1;
+        }
+    }
+
+    /// Forgets the blocks a failed move would have made and the missing block
+    /// that failed it. The blocks it refers to are left as they were.
+    fn forget_failed(
+        &mut self,
+        move_: &Move,
+        error: &InterpreterError,
+        unknown: &mut HashSet<BlockId>,
+    ) {
+        if let InterpreterError::BlockNonExistent(missing, _) = error {
+            unknown.insert(missing.clone());
+        }
+
+        let children = match move_ {
+            Move::LCut { block_id, .. } => (0..2)
+                .map(|id| BlockId::new_with_prev(block_id, id))
+                .collect(),
+            Move::PCut { block_id, .. } => (0..4)
+                .map(|id| BlockId::new_with_prev(block_id, id))
+                .collect(),
+            Move::Merge { .. } => {
+                self.counter += 1;
+                vec![BlockId::new(self.counter - 1)]
+            }
+            Move::Color { .. } | Move::Swap { .. } => Vec::new(),
+        };
+        unknown.extend(children);
+    }
+
+    /// Executes a single move, notifying observers and recording how to undo it.
+    fn step(&mut self, move_: &Move) -> InterpreterResult<MoveRecord> {
+        let canvas_size = (self.height * self.width) as u64;
+        let index = self.moves_run;
+
+        for observer in self.observers.iter_mut() {
+            observer.before_move(index, move_);
+        }
+
+        let undo = self.history.as_ref().map(|_| self.undo_entry(move_));
+        let effect = match self.execute(move_.clone()) {
+            Ok(effect) => effect,
+            Err(e) => {
+                for observer in self.observers.iter_mut() {
+                    observer.on_error(index, move_, &e);
+                }
+                return Err(e);
+            }
+        };
+        let cost = self
+            .cost_model
+            .cost(effect.kind, canvas_size, effect.block_size);
+        self.total_cost += cost;
+        self.moves_run += 1;
+
+        if let (Some(history), Some(mut undo)) = (&mut self.history, undo) {
+            undo.produced = effect.produced.iter().map(|(id, _)| id.clone()).collect();
+            undo.cost = cost;
+            history.undo.push(undo);
+        }
+
+        let record = MoveRecord {
+            move_: move_.clone(),
+            kind: effect.kind,
+            base_cost: self.cost_model.base_cost(effect.kind),
+            block_size: effect.block_size,
+            cost,
+            position: move_.position().clone(),
+        };
+
+        let event = MoveEvent {
+            index,
+            record: &record,
+            effect: &effect,
+            total_cost: self.total_cost,
+            width: self.width,
+            height: self.height,
+            canvas: &self.pixels,
+        };
+        for observer in self.observers.iter_mut() {
+            observer.after_move(&event);
+        }
+
+        Ok(record)
+    }
+
+    /// Turns undo, redo and checkpoints on or off, turning them off drops the history.
+    pub fn record_history(&mut self, enabled: bool) {
+        match (enabled, &self.history) {
+            (true, None) => self.history = Some(History::default()),
+            (false, _) => self.history = None,
+            _ => (),
+        }
+    }
+
+    /// Takes back the last move, returning it, or `None` if there's nothing to undo.
+    pub fn undo(&mut self) -> Option<Move> {
+        let entry = self.history.as_mut()?.undo.pop()?;
+
+        for block_id in &entry.produced {
+            self.blocks.remove(block_id);
+        }
+        for (block_id, block) in entry.consumed {
+            self.blocks.insert(block_id, block);
+        }
+        for (shape, pixels) in entry.pixels {
+            self.write_pixels(&shape, &pixels);
+        }
+        self.counter = entry.counter;
+        self.total_cost -= entry.cost;
+        self.moves_run -= 1;
+
+        if let Some(history) = &mut self.history {
+            history.redo.push(entry.move_.clone());
+        }
+        Some(entry.move_)
+    }
+
+    /// Replays the last undone move, returning `None` if there's nothing to redo.
+    pub fn redo(&mut self) -> InterpreterResult<Option<MoveRecord>> {
+        let move_ = match self.history.as_mut().and_then(|history| history.redo.pop()) {
+            Some(move_) => move_,
+            None => return Ok(None),
+        };
+
+        self.step(&move_).map(Some)
+    }
+
+    /// Saves the current state under `name`, replacing any checkpoint with the same name.
+    /// This turns history on if it isn't already.
+    pub fn checkpoint(&mut self, name: &str) {
+        let snapshot = Snapshot {
+            blocks: self.blocks.clone(),
+            counter: self.counter,
+            pixels: self.pixels.clone(),
+            total_cost: self.total_cost,
+            moves_run: self.moves_run,
+        };
+
+        self.history
+            .get_or_insert_with(History::default)
+            .checkpoints
+            .insert(name.to_string(), snapshot);
+    }
+
+    /// Restores a checkpoint, the undo and redo stacks are cleared.
+    pub fn restore(&mut self, name: &str) -> InterpreterResult<()> {
+        let history = self
+            .history
+            .as_mut()
+            .ok_or_else(|| InterpreterError::NoSuchCheckpoint(name.to_string()))?;
+        let snapshot = history
+            .checkpoints
+            .get(name)
+            .cloned()
+            .ok_or_else(|| InterpreterError::NoSuchCheckpoint(name.to_string()))?;
+
+        history.undo.clear();
+        history.redo.clear();
+        self.blocks = snapshot.blocks;
+        self.counter = snapshot.counter;
+        self.pixels = snapshot.pixels;
+        self.total_cost = snapshot.total_cost;
+        self.moves_run = snapshot.moves_run;
+        Ok(())
+    }
+
+    /// Returns the names of the saved checkpoints, sorted.
+    pub fn checkpoints(&self) -> Vec<String> {
+        let mut names = self
+            .history
+            .iter()
+            .flat_map(|history| history.checkpoints.keys().cloned())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    /// Copies the blocks a move references, and the pixels under them if the move paints.
+    fn undo_entry(&self, move_: &Move) -> UndoEntry {
+        let consumed = move_
+            .block_ids()
+            .into_iter()
+            .filter_map(|id| Some((id.clone(), self.blocks.get(id)?.clone())))
+            .collect::<Vec<_>>();
+
+        let pixels = match move_ {
+            Move::Color { .. } | Move::Swap { .. } => consumed
+                .iter()
+                .map(|(_, block)| {
+                    let shape = block.shape().clone();
+                    let pixels = self.read_pixels(&shape);
+                    (shape, pixels)
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        UndoEntry {
+            move_: move_.clone(),
+            consumed,
+            produced: Vec::new(),
+            pixels,
+            counter: self.counter,
+            cost: 0,
+        }
+    }
+
+    /// The cost of every move executed so far, across calls to `interpret`.
+    pub fn total_cost(&self) -> u64 {
+        self.total_cost
+    }
+
+    pub fn blocks(&self) -> &HashMap<BlockId, Block> {
+        &self.blocks
+    }
+
+    /// Returns the color at `(x, y)`, measured from the bottom-left corner.
+    pub fn pixel(&self, x: u64, y: u64) -> Option<Color> {
+        if x < self.width as u64 && y < self.height as u64 {
+            Some(self.pixels[self.pixel_index(x, y)])
+        } else {
+            None
+        }
+    }
+
+    /// Returns a copy of the painted canvas.
+    pub fn canvas(&self) -> Image {
+        Image::new(self.width, self.height, self.pixels.clone())
+    }
+
+    /// Exports the current blocks in the initial configuration format.
+    pub fn to_config(&self) -> ConfigResult<InitialConfig> {
+        let mut blocks = self.blocks.iter().collect::<Vec<_>>();
+        blocks.sort_by_key(|(block_id, _)| block_id.path());
+
+        let blocks = blocks
+            .into_iter()
+            .map(|(block_id, block)| match block {
+                Block::Simple { shape, fill } => {
+                    let (color, png_bottom_left_point) = match *fill {
+                        Fill::Color(Color(r, g, b, a)) => (Some([r, g, b, a]), None),
+                        Fill::Image { offset } => (None, Some([offset.x, offset.y])),
+                    };
+                    Ok(InitialBlock {
+                        block_id: block_id.path_string(),
+                        bottom_left: [shape.bl.x, shape.bl.y],
+                        top_right: [shape.tr.x, shape.tr.y],
+                        color,
+                        png_bottom_left_point,
+                    })
+                }
+                Block::Complex { .. } => Err(ConfigError::ComplexBlock(block_id.clone())),
+            })
+            .collect::<ConfigResult<Vec<_>>>()?;
+
+        Ok(InitialConfig {
+            width: self.width,
+            height: self.height,
+            blocks,
+        })
+    }
+
+    /// Executes a move, nothing is changed if it fails.
+    fn execute(&mut self, m: Move) -> InterpreterResult<MoveEffect> {
+        match m {
             Move::LCut {
                 block_id,
                 orientation,
                 line_no,
                 position,
             } => {
-                let parent = self.get_block(&block_id, &position)?;
+                let parent_block = self.get_block(&block_id, &position)?;
+                let parent = parent_block.shape().clone();
                 let size = parent.size();
                 let bounds = (parent.tl, parent.br);
                 let n = line_no;
 
-                let mut zero: BlockData = BlockData {
-                    tl: Point::new(0, 0),
-                    tr: Point::new(0, 0),
-                    bl: Point::new(0, 0),
-                    br: Point::new(0, 0),
-                };
-                let mut one: BlockData = BlockData {
-                    tl: Point::new(0, 0),
-                    tr: Point::new(0, 0),
-                    bl: Point::new(0, 0),
-                    br: Point::new(0, 0),
-                };
-
-                match orientation {
+                let (zero, one) = match orientation {
                     Orientation::Vertical => {
-                        let mut bisector = (parent.tl, parent.bl);
-                        bisector.0 = bisector.0.move_right(n);
-                        bisector.1 = bisector.1.move_right(n);
+                        // The bisector runs from the bottom to the top edge at absolute x = n.
+                        let bisector = (Point::new(n, parent.tl.y), Point::new(n, parent.bl.y));
 
-                        if !(bisector.0.within(bounds) && bisector.1.within(bounds)) {
+                        if !(parent.bl.x < n && n < parent.br.x) {
                             return Err(InterpreterError::OutOfBounds(
-                                bisector.0, bounds, position,
+                                bisector.1, bounds, position,
                             ));
                         }
 
-                        zero = BlockData {
+                        let left = BlockData {
                             tl: parent.tl,
                             bl: parent.bl,
                             tr: bisector.0,
                             br: bisector.1,
                         };
 
-                        one = BlockData {
+                        let right = BlockData {
                             tl: bisector.0,
                             bl: bisector.1,
                             tr: parent.tr,
                             br: parent.br,
                         };
+
+                        (left, right)
                     }
                     Orientation::Horizontal => {
-                        let bisector_0 = Point::new(parent.tl.x + n, parent.tl.y);
-                        let bisector_1 = Point::new(parent.tr.x + n, parent.tr.y);
-                        let bisector = (bisector_0, bisector_1);
+                        // The bisector runs from the left to the right edge at absolute y = n.
+                        let bisector = (Point::new(parent.bl.x, n), Point::new(parent.br.x, n));
+
+                        if !(parent.bl.y < n && n < parent.tl.y) {
+                            return Err(InterpreterError::OutOfBounds(
+                                bisector.0, bounds, position,
+                            ));
+                        }
+
+                        let bottom = BlockData {
+                            tl: bisector.0,
+                            tr: bisector.1,
+                            bl: parent.bl,
+                            br: parent.br,
+                        };
 
-                        zero = BlockData {
+                        let top = BlockData {
                             tl: parent.tl,
-                            bl: bisector.0,
                             tr: parent.tr,
+                            bl: bisector.0,
                             br: bisector.1,
                         };
+
+                        (bottom, top)
                     }
-                }
+                };
 
-                self.blocks.insert(
-                    BlockId {
-                        prev: Some(Box::new(block_id.clone())),
-                        id: 0,
-                    },
-                    zero,
-                );
+                let zero_id = BlockId::new_with_prev(&block_id, 0);
+                let one_id = BlockId::new_with_prev(&block_id, 1);
 
-                self.blocks.insert(
-                    BlockId {
-                        prev: Some(Box::new(block_id.clone())),
-                        id: 1,
-                    },
-                    one,
-                );
+                self.blocks
+                    .insert(zero_id.clone(), parent_block.part(zero.clone()));
+                self.blocks
+                    .insert(one_id.clone(), parent_block.part(one.clone()));
                 self.blocks.remove(&block_id);
-                Ok((7, size))
+
+                Ok(MoveEffect {
+                    kind: MoveKind::LineCut,
+                    block_size: size,
+                    consumed: vec![(block_id, parent)],
+                    produced: vec![(zero_id, zero), (one_id, one)],
+                })
             }
             Move::PCut {
                 block_id,
                 point,
                 position,
             } => {
-                let parent = self.get_block(&block_id, &position)?;
+                let parent_block = self.get_block(&block_id, &position)?;
+                let parent = parent_block.shape().clone();
                 let bounds = (parent.tl, parent.br);
                 if !within((point.x, point.y), bounds) {
                     return Err(InterpreterError::OutOfBounds(
@@ -238,25 +676,43 @@ impl Interpreter {
                 };
 
                 self.blocks.remove(&block_id);
-                self.set_block(q0_id, q0);
-                self.set_block(q1_id, q1);
-                self.set_block(q2_id, q2);
-                self.set_block(q3_id, q3);
+                let produced = vec![(q0_id, q0), (q1_id, q1), (q2_id, q2), (q3_id, q3)];
+                for (id, shape) in &produced {
+                    self.set_block(id.clone(), parent_block.part(shape.clone()));
+                }
 
-                Ok((10, parent.size()))
+                Ok(MoveEffect {
+                    kind: MoveKind::PointCut,
+                    block_size: parent.size(),
+                    consumed: vec![(block_id, parent)],
+                    produced,
+                })
             }
             Move::Color {
                 block_id,
                 color,
                 position,
             } => {
-                let block = self.get_block(&block_id, &position)?;
-                let block = block;
-                let size = block.size();
-                self.color_block(block.clone(), color);
-                self.blocks.insert(block_id, block);
+                let shape = self.get_block(&block_id, &position)?.shape().clone();
+                let size = shape.size();
+                if self.paint {
+                    self.color_block(shape.clone(), color);
+                }
+                // Coloring flattens a complex block back into a simple one.
+                self.blocks.insert(
+                    block_id.clone(),
+                    Block::Simple {
+                        shape: shape.clone(),
+                        fill: Fill::Color(color),
+                    },
+                );
 
-                Ok((5, size))
+                Ok(MoveEffect {
+                    kind: MoveKind::Color,
+                    block_size: size,
+                    consumed: vec![(block_id.clone(), shape.clone())],
+                    produced: vec![(block_id, shape)],
+                })
             }
             Move::Swap {
                 block_id_1,
@@ -266,15 +722,32 @@ impl Interpreter {
                 let block_1 = self.get_block(&block_id_1, &position)?;
                 let block_2 = self.get_block(&block_id_2, &position)?;
 
-                if !block_1.same_shape(&block_2) {
+                let (shape_1, shape_2) = (block_1.shape(), block_2.shape());
+
+                if !shape_1.same_shape(shape_2) {
                     return Err(InterpreterError::NotTheSameSize(
                         block_id_1, block_id_2, position,
                     ));
                 }
 
-                self.blocks.insert(block_id_1, block_2.to_owned());
-                self.blocks.insert(block_id_2, block_1.to_owned());
-                Ok((3, block_1.size() + block_2.size()))
+                if self.paint {
+                    self.swap_pixels(shape_1, shape_2);
+                }
+                self.blocks
+                    .insert(block_id_1.clone(), block_1.moved_to(shape_2.clone()));
+                self.blocks
+                    .insert(block_id_2.clone(), block_2.moved_to(shape_1.clone()));
+
+                // Swaps are charged for one of the blocks, as the contest does.
+                Ok(MoveEffect {
+                    kind: MoveKind::Swap,
+                    block_size: shape_1.size(),
+                    consumed: vec![
+                        (block_id_1.clone(), shape_1.clone()),
+                        (block_id_2.clone(), shape_2.clone()),
+                    ],
+                    produced: vec![(block_id_1, shape_2.clone()), (block_id_2, shape_1.clone())],
+                })
             }
             Move::Merge {
                 block_id_1,
@@ -284,56 +757,101 @@ impl Interpreter {
                 let block_1 = self.get_block(&block_id_1, &position)?;
                 let block_2 = self.get_block(&block_id_2, &position)?;
 
-                if !block_1.same_shape(&block_2) {
-                    return Err(InterpreterError::NotTheSameSize(
+                let (shape_1, shape_2) = (block_1.shape(), block_2.shape());
+
+                if !shape_1.touches(shape_2) {
+                    return Err(InterpreterError::NotAdjoint(
                         block_id_1, block_id_2, position,
                     ));
-                } else if !block_1.adjoint(&block_2) {
-                    return Err(InterpreterError::NotAdjoint(
+                } else if !shape_1.adjoint(shape_2) {
+                    return Err(InterpreterError::EdgeMismatch(
                         block_id_1, block_id_2, position,
                     ));
                 }
 
-                let new_block_id = BlockId {
-                    prev: None,
-                    id: self.counter,
-                };
+                // Merged blocks get a fresh top-level id from the global counter.
+                let new_block_id = BlockId::new(self.counter);
+                self.counter += 1;
 
-                let new_block = block_1.join(&block_2);
+                let new_block = block_1.merge(&block_2);
+                let new_shape = new_block.shape().clone();
 
                 self.blocks.remove(&block_id_1);
                 self.blocks.remove(&block_id_2);
-                self.blocks.insert(new_block_id, new_block);
+                self.blocks.insert(new_block_id.clone(), new_block);
 
-                Ok((1, block_1.size() + block_2.size()))
+                // Merges are charged for the larger block, as the contest does.
+                Ok(MoveEffect {
+                    kind: MoveKind::Merge,
+                    block_size: shape_1.size().max(shape_2.size()),
+                    consumed: vec![(block_id_1, shape_1.clone()), (block_id_2, shape_2.clone())],
+                    produced: vec![(new_block_id, new_shape)],
+                })
             }
-        };
-        self.counter += 1;
-        res
+        }
     }
 
     fn color_block(&mut self, block: BlockData, color: Color) {
         let BlockData { tl, tr, bl, .. } = block;
         for x in tl.x..tr.x {
-            for y in tl.y..bl.y {
+            for y in bl.y..tl.y {
                 self.color_pixel(x, y, color)
             }
         }
     }
 
-    fn color_pixel(&mut self, x: u64, y: u64, color: Color) {
-        let x = x as usize;
-        let y = y as usize;
+    /// Copies the source region starting at `offset` into the canvas under `shape`.
+    fn copy_from_source(&mut self, shape: &BlockData, offset: Point, source: &Image) {
+        for dy in 0..shape.height() {
+            for dx in 0..shape.width() {
+                let from = (offset.x + dx) as usize + (offset.y + dy) as usize * source.width;
+                let to = self.pixel_index(shape.bl.x + dx, shape.bl.y + dy);
+                self.pixels[to] = source.pixels[from];
+            }
+        }
+    }
+
+    /// Returns the pixels under `shape`, bottom row first.
+    fn read_pixels(&self, shape: &BlockData) -> Vec<Color> {
+        let mut pixels = Vec::with_capacity(shape.size() as usize);
+        for y in shape.bl.y..shape.tr.y {
+            let start = self.pixel_index(shape.bl.x, y);
+            pixels.extend_from_slice(&self.pixels[start..start + shape.width() as usize]);
+        }
+        pixels
+    }
 
-        let index = x + (y * self.width);
+    /// Writes pixels returned by `read_pixels` back under `shape`.
+    fn write_pixels(&mut self, shape: &BlockData, pixels: &[Color]) {
+        let width = shape.width() as usize;
+        for (row, y) in (shape.bl.y..shape.tr.y).enumerate() {
+            let start = self.pixel_index(shape.bl.x, y);
+            self.pixels[start..start + width]
+                .copy_from_slice(&pixels[row * width..(row + 1) * width]);
+        }
+    }
+
+    /// Exchanges the pixels of two blocks with the same shape.
+    fn swap_pixels(&mut self, block_1: &BlockData, block_2: &BlockData) {
+        for dy in 0..block_1.height() {
+            for dx in 0..block_1.width() {
+                let i = self.pixel_index(block_1.bl.x + dx, block_1.bl.y + dy);
+                let j = self.pixel_index(block_2.bl.x + dx, block_2.bl.y + dy);
+                self.pixels.swap(i, j);
+            }
+        }
+    }
+
+    fn pixel_index(&self, x: u64, y: u64) -> usize {
+        x as usize + (y as usize * self.width)
+    }
+
+    fn color_pixel(&mut self, x: u64, y: u64, color: Color) {
+        let index = self.pixel_index(x, y);
         self.pixels[index] = color;
     }
 
-    fn get_block(
-        &mut self,
-        block_id: &BlockId,
-        position: &Position,
-    ) -> InterpreterResult<BlockData> {
+    fn get_block(&mut self, block_id: &BlockId, position: &Position) -> InterpreterResult<Block> {
         if let Some(block) = self.blocks.get(block_id) {
             Ok(block.clone())
         } else {
@@ -344,13 +862,183 @@ impl Interpreter {
         }
     }
 
-    fn set_block(&mut self, block_id: BlockId, data: BlockData) {
+    fn set_block(&mut self, block_id: BlockId, data: Block) {
         self.blocks.insert(block_id, data);
     }
 }
 
+/// What a move did to the blocks and what it's charged for.
+#[derive(Debug, Clone)]
+pub struct MoveEffect {
+    pub kind: MoveKind,
+    /// The size of the block the move is charged for.
+    pub block_size: u64,
+    /// The blocks the move used up, with their shapes before the move.
+    pub consumed: Vec<(BlockId, BlockData)>,
+    /// The blocks the move left behind, with their shapes after the move.
+    pub produced: Vec<(BlockId, BlockData)>,
+}
+
+/// A block's shape together with what is painted inside it.
+#[derive(Debug, Clone)]
+pub enum Block {
+    /// A block with a single fill.
+    Simple { shape: BlockData, fill: Fill },
+    /// A merged block whose children keep their own fills.
+    Complex {
+        shape: BlockData,
+        children: Vec<SubBlock>,
+    },
+}
+
+/// What a simple block or sub-block is painted with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fill {
+    Color(Color),
+    /// A region of the source image, `offset` is the source point under the bottom-left corner.
+    /// The pixels themselves live in the canvas and travel with the block.
+    Image {
+        offset: Point,
+    },
+}
+
+impl std::fmt::Display for Fill {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Fill::Color(color) => write!(f, "color {color}"),
+            Fill::Image { offset } => write!(f, "image from [{}, {}]", offset.x, offset.y),
+        }
+    }
+}
+
+/// A filled rectangle inside a complex block.
+#[derive(Debug, Clone)]
+pub struct SubBlock {
+    pub shape: BlockData,
+    pub fill: Fill,
+}
+
+impl SubBlock {
+    /// Returns the part of the sub-block covered by `shape`, if they overlap.
+    fn part(&self, shape: &BlockData) -> Option<SubBlock> {
+        let part = self.shape.intersect(shape)?;
+        let fill = match self.fill {
+            Fill::Color(color) => Fill::Color(color),
+            Fill::Image { offset } => Fill::Image {
+                offset: Point::new(
+                    offset.x + part.bl.x - self.shape.bl.x,
+                    offset.y + part.bl.y - self.shape.bl.y,
+                ),
+            },
+        };
+
+        Some(SubBlock { shape: part, fill })
+    }
+
+    /// Returns true if both sub-blocks would look like one when joined.
+    fn continues(&self, rhs: &SubBlock) -> bool {
+        match (self.fill, rhs.fill) {
+            (Fill::Color(lhs), Fill::Color(rhs)) => lhs == rhs,
+            (Fill::Image { offset: lhs }, Fill::Image { offset: rhs_offset }) => {
+                let shift = |offset: Point, shape: &BlockData| {
+                    (
+                        offset.x as i64 - shape.bl.x as i64,
+                        offset.y as i64 - shape.bl.y as i64,
+                    )
+                };
+                shift(lhs, &self.shape) == shift(rhs_offset, &rhs.shape)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Block {
+    pub fn shape(&self) -> &BlockData {
+        match self {
+            Block::Simple { shape, .. } | Block::Complex { shape, .. } => shape,
+        }
+    }
+
+    /// Returns the filled rectangles making up the block.
+    pub fn children(&self) -> Vec<SubBlock> {
+        match self {
+            Block::Simple { shape, fill } => vec![SubBlock {
+                shape: shape.clone(),
+                fill: *fill,
+            }],
+            Block::Complex { children, .. } => children.clone(),
+        }
+    }
+
+    /// Returns the part of the block covered by `shape`, keeping its children's fills.
+    pub fn part(&self, shape: BlockData) -> Block {
+        let children = self
+            .children()
+            .iter()
+            .filter_map(|child| child.part(&shape))
+            .collect();
+
+        Block::from_children(shape, children)
+    }
+
+    /// Joins two blocks, the result is only complex if their fills differ.
+    pub fn merge(&self, rhs: &Block) -> Block {
+        let shape = self.shape().join(rhs.shape());
+        let mut children = self.children();
+        children.extend(rhs.children());
+
+        Block::from_children(shape, children)
+    }
+
+    /// Moves the block and its children to `shape`, which must have the same dimensions.
+    pub fn moved_to(&self, shape: BlockData) -> Block {
+        let from = self.shape().bl;
+        let to = shape.bl;
+
+        let children = self
+            .children()
+            .into_iter()
+            .map(|child| {
+                let bl = Point::new(
+                    child.shape.bl.x + to.x - from.x,
+                    child.shape.bl.y + to.y - from.y,
+                );
+                let tr = Point::new(bl.x + child.shape.width(), bl.y + child.shape.height());
+
+                SubBlock {
+                    shape: BlockData::from_corners(bl, tr),
+                    fill: child.fill,
+                }
+            })
+            .collect();
+
+        Block::from_children(shape, children)
+    }
+
+    fn from_children(shape: BlockData, children: Vec<SubBlock>) -> Block {
+        match children.first() {
+            Some(first) if children.iter().all(|child| first.continues(child)) => {
+                let fill = match first.fill {
+                    // Re-anchor the source offset at the joined block's bottom-left corner.
+                    Fill::Image { offset } => Fill::Image {
+                        offset: Point::new(
+                            offset.x + shape.bl.x - first.shape.bl.x,
+                            offset.y + shape.bl.y - first.shape.bl.y,
+                        ),
+                    },
+                    fill => fill,
+                };
+
+                Block::Simple { shape, fill }
+            }
+            _ => Block::Complex { shape, children },
+        }
+    }
+}
+
 #[derive(Debug, Error, Clone)]
-#[error("Block{{{tl}, {tr}, {bl}, {br}")]
+#[error("[{}, {}] - [{}, {}]", bl.x, bl.y, tr.x, tr.y)]
 pub struct BlockData {
     tl: Point,
     tr: Point,
@@ -359,57 +1047,80 @@ pub struct BlockData {
 }
 
 impl BlockData {
+    pub fn bl(&self) -> Point {
+        self.bl
+    }
+
+    pub fn tr(&self) -> Point {
+        self.tr
+    }
+
     pub fn size(&self) -> u64 {
         (self.tl.y - self.bl.y) * (self.tr.x - self.tl.x)
     }
 
+    pub fn width(&self) -> u64 {
+        self.tr.x - self.tl.x
+    }
+
+    pub fn height(&self) -> u64 {
+        self.tl.y - self.bl.y
+    }
+
+    /// Returns true if both blocks have the same width and height.
     pub fn same_shape(&self, rhs: &BlockData) -> bool {
-        self.size() == rhs.size()
+        self.width() == rhs.width() && self.height() == rhs.height()
     }
 
-    pub fn adjoint(&self, rhs: &BlockData) -> bool {
-        let own = [self.tl, self.tr, self.br, self.bl];
-        let other = [rhs.tl, rhs.tr, rhs.br, rhs.bl];
+    /// Returns true if the blocks share a boundary segment of non-zero length.
+    pub fn touches(&self, rhs: &BlockData) -> bool {
+        let side_by_side = (self.tr.x == rhs.bl.x || rhs.tr.x == self.bl.x)
+            && self.bl.y.max(rhs.bl.y) < self.tr.y.min(rhs.tr.y);
+        let stacked = (self.tr.y == rhs.bl.y || rhs.tr.y == self.bl.y)
+            && self.bl.x.max(rhs.bl.x) < self.tr.x.min(rhs.tr.x);
 
-        let mut common = 0;
+        side_by_side || stacked
+    }
 
-        for point in own {
-            for point_ in other {
-                if point == point_ {
-                    common += 1;
-                }
-            }
-        }
+    /// Returns true if the blocks share a full edge of equal length.
+    pub fn adjoint(&self, rhs: &BlockData) -> bool {
+        let side_by_side = (self.tr.x == rhs.bl.x || rhs.tr.x == self.bl.x)
+            && self.bl.y == rhs.bl.y
+            && self.tr.y == rhs.tr.y;
+        let stacked = (self.tr.y == rhs.bl.y || rhs.tr.y == self.bl.y)
+            && self.bl.x == rhs.bl.x
+            && self.tr.x == rhs.tr.x;
 
-        common > 1
+        side_by_side || stacked
     }
 
+    /// Returns the bounding rectangle of both blocks.
     pub fn join(&self, rhs: &BlockData) -> BlockData {
-        let tl = if self.tl.x < rhs.tl.x {
-            self.tl
-        } else {
-            rhs.tl
-        };
+        let bl = Point::new(self.bl.x.min(rhs.bl.x), self.bl.y.min(rhs.bl.y));
+        let tr = Point::new(self.tr.x.max(rhs.tr.x), self.tr.y.max(rhs.tr.y));
 
-        let tr = if self.tr.x > rhs.tr.x {
-            self.tr
-        } else {
-            rhs.tr
-        };
+        BlockData::from_corners(bl, tr)
+    }
 
-        let bl = if self.bl.x < rhs.bl.x {
-            self.bl
-        } else {
-            rhs.bl
-        };
+    /// Returns the overlap of both blocks, if it isn't empty.
+    pub fn intersect(&self, rhs: &BlockData) -> Option<BlockData> {
+        let bl = Point::new(self.bl.x.max(rhs.bl.x), self.bl.y.max(rhs.bl.y));
+        let tr = Point::new(self.tr.x.min(rhs.tr.x), self.tr.y.min(rhs.tr.y));
 
-        let br = if self.br.x > rhs.br.x {
-            self.br
+        if bl.x < tr.x && bl.y < tr.y {
+            Some(BlockData::from_corners(bl, tr))
         } else {
-            rhs.br
-        };
+            None
+        }
+    }
 
-        BlockData { tl, tr, bl, br }
+    pub fn from_corners(bl: Point, tr: Point) -> BlockData {
+        BlockData {
+            tl: Point::new(bl.x, tr.y),
+            tr,
+            bl,
+            br: Point::new(tr.x, bl.y),
+        }
     }
 }
 
@@ -419,10 +1130,531 @@ pub enum InterpreterError {
     OutOfBounds(Point, (Point, Point), Position),
     #[error("Block '{0}' doesn't exist, {1}.")]
     BlockNonExistent(BlockId, Position),
-    #[error("Block {0} and {1} aren't of the same size, {2}.")]
+    #[error("Block {0} and {1} don't have the same width and height, {2}.")]
     NotTheSameSize(BlockId, BlockId, Position),
     #[error("Block {0} and {1} aren't next to each other, {2}.")]
     NotAdjoint(BlockId, BlockId, Position),
+    #[error("Block {0} and {1} touch but don't share an edge of the same length, {2}.")]
+    EdgeMismatch(BlockId, BlockId, Position),
+    #[error("There's no checkpoint named '{0}'.")]
+    NoSuchCheckpoint(String),
+}
+
+impl InterpreterError {
+    /// The position of the failing move, if the error came from one.
+    pub fn position(&self) -> Option<&Position> {
+        use InterpreterError::*;
+        match self {
+            OutOfBounds(_, _, position)
+            | BlockNonExistent(_, position)
+            | NotTheSameSize(_, _, position)
+            | NotAdjoint(_, _, position)
+            | EdgeMismatch(_, _, position) => Some(position),
+            NoSuchCheckpoint(_) => None,
+        }
+    }
+}
+
+impl Diagnostic for InterpreterError {
+    fn code<'a>(&'a self) -> Option<Box<dyn std::fmt::Display + 'a>> {
+        Some(Box::new("isl::interpreter"))
+    }
+
+    fn source_code(&self) -> Option<&dyn SourceCode> {
+        self.position()
+            .map(|position| position.src.as_ref() as &dyn SourceCode)
+    }
+
+    /// Underlines the move's name and every block it refers to.
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = LabeledSpan> + '_>> {
+        use InterpreterError::*;
+        let position = self.position()?;
+
+        let reason = match self {
+            OutOfBounds(..) => "cuts outside the block",
+            BlockNonExistent(..) => "refers to a missing block",
+            NotTheSameSize(..) => "swaps blocks of different shapes",
+            NotAdjoint(..) => "merges blocks that aren't adjacent",
+            EdgeMismatch(..) => "merges blocks with uneven edges",
+            NoSuchCheckpoint(_) => unreachable!("checkpoints have no position"),
+        };
+        let mut labels = vec![LabeledSpan::new_with_span(
+            Some(reason.to_string()),
+            position.span(),
+        )];
+
+        for (block_id, span) in &position.blocks {
+            let label = match self {
+                BlockNonExistent(missing, _) if missing == block_id => {
+                    String::from("doesn't exist")
+                }
+                _ => format!("block {block_id}"),
+            };
+            labels.push(LabeledSpan::new_with_span(Some(label), *span));
+        }
+
+        Some(Box::new(labels.into_iter()))
+    }
 }
 
 pub type InterpreterResult<T> = Result<T, InterpreterError>;
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{parser::Parser, scanner::Scanner};
+    use std::{cell::RefCell, rc::Rc};
+
+    /// Scans and parses a program that's known to be valid.
+    pub(crate) fn parse(src: &str) -> Vec<Move> {
+        Parser::parse_tokens(&Scanner::scan_str(src).unwrap()).unwrap()
+    }
+
+    /// A white 100x100 canvas.
+    pub(crate) fn blank() -> Interpreter {
+        Interpreter::with_canvas(100, 100, Color(255, 255, 255, 255))
+    }
+
+    /// A blank canvas and the program to run on it.
+    pub(crate) fn program(src: &str) -> (Interpreter, Vec<Move>) {
+        (blank(), parse(src))
+    }
+
+    fn run(src: &str) -> (Interpreter, InterpreterResult<RunReport>) {
+        let (mut interpreter, moves) = program(src);
+        let res = interpreter.interpret(&moves);
+
+        (interpreter, res)
+    }
+
+    fn block(interpreter: &Interpreter, id: &[u64]) -> BlockData {
+        let block_id = BlockId::from_path(id).unwrap();
+
+        interpreter
+            .blocks
+            .get(&block_id)
+            .unwrap_or_else(|| panic!("Block {block_id} doesn't exist"))
+            .shape()
+            .clone()
+    }
+
+    #[test]
+    fn horizontal_cut_splits_bottom_and_top() {
+        let (interpreter, res) = run("cut [0] [y] [30]\ncut [0.1] [y] [60]");
+        res.unwrap();
+
+        let bottom = block(&interpreter, &[0, 0]);
+        assert_eq!(
+            (bottom.bl, bottom.tr),
+            (Point::new(0, 0), Point::new(100, 30))
+        );
+
+        let middle = block(&interpreter, &[0, 1, 0]);
+        assert_eq!(
+            (middle.bl, middle.tr),
+            (Point::new(0, 30), Point::new(100, 60))
+        );
+
+        let top = block(&interpreter, &[0, 1, 1]);
+        assert_eq!((top.bl, top.tr), (Point::new(0, 60), Point::new(100, 100)));
+    }
+
+    #[test]
+    fn vertical_cut_uses_absolute_x() {
+        let (interpreter, res) = run("cut [0] [x] [30]\ncut [0.1] [x] [60]");
+        res.unwrap();
+
+        let middle = block(&interpreter, &[0, 1, 0]);
+        assert_eq!(
+            (middle.bl, middle.tr),
+            (Point::new(30, 0), Point::new(60, 100))
+        );
+    }
+
+    #[test]
+    fn line_cuts_outside_the_block_are_rejected() {
+        let (_, res) = run("cut [0] [y] [30]\ncut [0.1] [y] [20]");
+        assert!(matches!(res, Err(InterpreterError::OutOfBounds(..))));
+
+        let (_, res) = run("cut [0] [x] [100]");
+        assert!(matches!(res, Err(InterpreterError::OutOfBounds(..))));
+    }
+
+    #[test]
+    fn merges_blocks_sharing_a_full_edge() {
+        let (interpreter, res) = run("cut [0] [x] [10]\nmerge [0.0] [0.1]");
+        res.unwrap();
+        let merged = interpreter.blocks.values().next().unwrap().shape().clone();
+        assert_eq!(interpreter.blocks.len(), 1);
+        assert_eq!(
+            (merged.bl, merged.tr),
+            (Point::new(0, 0), Point::new(100, 100))
+        );
+
+        let (interpreter, res) = run("cut [0] [y] [40]\nmerge [0.1] [0.0]");
+        res.unwrap();
+        let merged = interpreter.blocks.values().next().unwrap().shape().clone();
+        assert_eq!(
+            (merged.bl, merged.tr),
+            (Point::new(0, 0), Point::new(100, 100))
+        );
+    }
+
+    #[test]
+    fn merge_rejects_mismatched_or_distant_blocks() {
+        let (_, res) = run("cut [0] [50, 50]\ncut [0.0] [y] [20]\nmerge [0.0.1] [0.1]");
+        assert!(matches!(res, Err(InterpreterError::EdgeMismatch(..))));
+
+        let (_, res) = run("cut [0] [50, 50]\nmerge [0.0] [0.2]");
+        assert!(matches!(res, Err(InterpreterError::NotAdjoint(..))));
+    }
+
+    #[test]
+    fn merge_charges_the_larger_block() {
+        // 7 + 1 * 10000 / 5000, the sum of the two would cost 1.
+        let (_, res) = run("cut [0] [x] [50]\nmerge [0.0] [0.1]");
+        assert_eq!(res.unwrap().total_cost, 9);
+
+        // 7 + 1 * 10000 / 9000, the smaller block would cost 10.
+        let (_, res) = run("cut [0] [x] [10]\nmerge [0.0] [0.1]");
+        assert_eq!(res.unwrap().total_cost, 8);
+    }
+
+    #[test]
+    fn swap_moves_pixels_and_requires_identical_dimensions() {
+        let red = Color(255, 0, 0, 255);
+        let (interpreter, res) =
+            run("cut [0] [x] [50]\ncolor [0.0] [255, 0, 0, 255]\nswap [0.0] [0.1]");
+        res.unwrap();
+        let canvas = interpreter.canvas();
+        assert_ne!(canvas.pixels[0], red);
+        assert_eq!(canvas.pixels[99], red);
+
+        let swapped = block(&interpreter, &[0, 0]);
+        assert_eq!(
+            (swapped.bl, swapped.tr),
+            (Point::new(50, 0), Point::new(100, 100))
+        );
+
+        let (_, res) = run("cut [0] [20, 20]\nswap [0.1] [0.3]");
+        assert!(matches!(res, Err(InterpreterError::NotTheSameSize(..))));
+    }
+
+    #[test]
+    fn swap_charges_one_block() {
+        // 7 + 3 * 10000 / 5000, the sum of the two would cost 3.
+        let (_, res) = run("cut [0] [x] [50]\nswap [0.0] [0.1]");
+        assert_eq!(res.unwrap().total_cost, 13);
+    }
+
+    #[test]
+    fn merges_take_ids_from_the_global_counter() {
+        let (interpreter, res) = run(
+            "cut [0] [50, 50]\ncolor [0.0] [0, 0, 0, 255]\nmerge [0.0] [0.1]\nmerge [0.2] [0.3]\nmerge [1] [2]",
+        );
+        res.unwrap();
+
+        let merged = block(&interpreter, &[3]);
+        assert_eq!(
+            (merged.bl, merged.tr),
+            (Point::new(0, 0), Point::new(100, 100))
+        );
+        assert_eq!(interpreter.counter, 4);
+    }
+
+    #[test]
+    fn costs_scale_with_the_canvas_size() {
+        let moves = parse("cut [0] [x] [100]\ncolor [0.0] [0, 0, 0, 255]");
+        let mut interpreter = Interpreter::with_canvas(400, 200, Color(0, 0, 0, 0));
+
+        // 7 * 80000 / 80000 + 5 * 80000 / 20000
+        assert_eq!(interpreter.interpret(&moves).unwrap().total_cost, 27);
+        assert_eq!(interpreter.canvas().pixels[0], Color(0, 0, 0, 255));
+        assert_eq!(interpreter.canvas().pixels[399], Color(0, 0, 0, 0));
+
+        interpreter.set_cost_model(Box::new(BaseCosts::FULL_CONTEST));
+        let moves = parse("cut [0.1] [x] [200]\nswap [0.0] [0.1.0]\nmerge [0.0] [0.1.0]");
+        // 2 * 80000 / 60000 + 3 * 80000 / 20000 + 1 * 80000 / 20000
+        assert_eq!(
+            interpreter.interpret(&moves).unwrap().total_cost,
+            3 + 12 + 4
+        );
+    }
+
+    #[test]
+    fn merging_differently_colored_blocks_keeps_their_colors() {
+        let red = Color(255, 0, 0, 255);
+        let blue = Color(0, 0, 255, 255);
+        let (interpreter, res) = run(
+            "cut [0] [x] [50]\ncolor [0.0] [255, 0, 0, 255]\ncolor [0.1] [0, 0, 255, 255]\nmerge [0.0] [0.1]\ncut [1] [x] [25]",
+        );
+        res.unwrap();
+
+        let left = &interpreter.blocks[&BlockId::new_with_prev(&BlockId::new(1), 0)];
+        assert!(matches!(left, Block::Simple { fill, .. } if *fill == Fill::Color(red)));
+
+        let right = &interpreter.blocks[&BlockId::new_with_prev(&BlockId::new(1), 1)];
+        let children = right.children();
+        assert!(matches!(right, Block::Complex { .. }));
+        assert_eq!(children.len(), 2);
+        assert!(children
+            .iter()
+            .any(|child| child.fill == Fill::Color(red) && child.shape.width() == 25));
+        assert!(children
+            .iter()
+            .any(|child| child.fill == Fill::Color(blue) && child.shape.width() == 50));
+    }
+
+    #[test]
+    fn coloring_a_complex_block_flattens_it() {
+        let (interpreter, res) = run(
+            "cut [0] [x] [50]\ncolor [0.0] [255, 0, 0, 255]\nmerge [0.0] [0.1]\ncolor [1] [0, 255, 0, 255]",
+        );
+        res.unwrap();
+
+        let block = &interpreter.blocks[&BlockId::new(1)];
+        assert!(
+            matches!(block, Block::Simple { fill, .. } if *fill == Fill::Color(Color(0, 255, 0, 255)))
+        );
+    }
+
+    #[test]
+    fn loads_and_exports_initial_configurations() {
+        let json = r#"{
+            "width": 40,
+            "height": 20,
+            "blocks": [
+                { "blockId": "0", "bottomLeft": [0, 0], "topRight": [20, 20], "color": [255, 0, 0, 255] },
+                { "blockId": "1", "bottomLeft": [20, 0], "topRight": [40, 20], "color": [0, 0, 255, 255] }
+            ]
+        }"#;
+        let config = InitialConfig::from_json(json).unwrap();
+        let mut interpreter = Interpreter::from_config(&config, None).unwrap();
+
+        assert_eq!(interpreter.canvas().pixels[0], Color(255, 0, 0, 255));
+        assert_eq!(interpreter.canvas().pixels[39], Color(0, 0, 255, 255));
+        assert_eq!(interpreter.to_config().unwrap(), config);
+
+        let moves = parse("color [1] [0, 255, 0, 255]\nmerge [0] [1]\ncut [2] [x] [20]");
+        interpreter.interpret(&moves).unwrap();
+
+        let exported = interpreter.to_config().unwrap();
+        let ids = exported
+            .blocks
+            .iter()
+            .map(|b| b.block_id.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, ["2.0", "2.1"]);
+        assert_eq!(exported.blocks[1].color, Some([0, 255, 0, 255]));
+    }
+
+    #[test]
+    fn image_backed_blocks_keep_their_texture() {
+        // A 4x2 source whose pixels encode their own coordinates.
+        let source_pixels = (0..2)
+            .flat_map(|y| (0..4).map(move |x| Color(x, y, 0, 255)))
+            .collect();
+        let source = Image::new(4, 2, source_pixels);
+        let json = r#"{
+            "width": 4,
+            "height": 2,
+            "blocks": [
+                { "blockId": "0", "bottomLeft": [0, 0], "topRight": [2, 2], "pngBottomLeftPoint": [2, 0] },
+                { "blockId": "1", "bottomLeft": [2, 0], "topRight": [4, 2], "pngBottomLeftPoint": [0, 0] }
+            ]
+        }"#;
+        let config = InitialConfig::from_json(json).unwrap();
+        assert!(matches!(
+            Interpreter::from_config(&config, None),
+            Err(ConfigError::MissingSource(_))
+        ));
+
+        let mut interpreter = Interpreter::from_config(&config, Some(&source)).unwrap();
+        assert_eq!(interpreter.canvas().pixels[0], Color(2, 0, 0, 255));
+
+        let moves = parse("swap [0] [1]\nmerge [0] [1]\ncut [2] [x] [1]");
+        interpreter.interpret(&moves).unwrap();
+
+        // After the swap the canvas shows the source unchanged, so the merge is seamless.
+        assert_eq!(interpreter.canvas(), source);
+        let exported = interpreter.to_config().unwrap();
+        assert_eq!(exported.blocks[0].png_bottom_left_point, Some([0, 0]));
+        assert_eq!(exported.blocks[1].png_bottom_left_point, Some([1, 0]));
+    }
+
+    #[test]
+    fn reports_every_move() {
+        let (interpreter, res) = run("cut [0] [x] [50]\ncolor [0.1] [0, 0, 0, 255]");
+        let report = res.unwrap();
+
+        assert_eq!(report.moves.len(), 2);
+        let color = &report.moves[1];
+        assert_eq!(color.kind, MoveKind::Color);
+        assert_eq!(
+            (color.base_cost, color.block_size, color.cost),
+            (5, 5000, 10)
+        );
+        assert_eq!(color.position.line, 1);
+        assert_eq!(report.total_cost, 7 + 10);
+        assert_eq!(report.canvas, interpreter.canvas());
+        assert_eq!(report.blocks.len(), 2);
+    }
+
+    #[test]
+    fn observers_see_every_move() {
+        #[derive(Default)]
+        struct Recorder {
+            before: usize,
+            produced: Vec<Vec<BlockId>>,
+            errors: usize,
+        }
+
+        impl Observer for Recorder {
+            fn before_move(&mut self, _index: usize, _move_: &Move) {
+                self.before += 1;
+            }
+
+            fn after_move(&mut self, event: &MoveEvent) {
+                let ids = event.effect.produced.iter().map(|(id, _)| id.clone());
+                self.produced.push(ids.collect());
+            }
+
+            fn on_error(&mut self, _index: usize, _move_: &Move, _error: &InterpreterError) {
+                self.errors += 1;
+            }
+        }
+
+        let recorder = Rc::new(RefCell::new(Recorder::default()));
+        let (mut interpreter, moves) =
+            program("cut [0] [x] [50]\nmerge [0.0] [0.1]\ncolor [0] [0, 0, 0, 0]");
+        interpreter.add_observer(Box::new(recorder.clone()));
+        assert!(interpreter.interpret(&moves).is_err());
+
+        let recorder = recorder.borrow();
+        assert_eq!(recorder.before, 3);
+        assert_eq!(recorder.errors, 1);
+        assert_eq!(
+            recorder.produced,
+            vec![
+                vec![
+                    BlockId::from_path(&[0, 0]).unwrap(),
+                    BlockId::from_path(&[0, 1]).unwrap()
+                ],
+                vec![BlockId::new(1)],
+            ]
+        );
+    }
+
+    #[test]
+    fn undo_and_redo_restore_blocks_pixels_and_cost() {
+        let (mut interpreter, moves) =
+            program("cut [0] [x] [50]\ncolor [0.0] [255, 0, 0, 255]\nmerge [0.0] [0.1]");
+        #[derive(Debug, Default)]
+        struct Totals(Vec<(usize, u64)>);
+
+        impl Observer for Totals {
+            fn after_move(&mut self, event: &MoveEvent) {
+                self.0.push((event.index, event.total_cost));
+            }
+        }
+
+        let totals = Rc::new(RefCell::new(Totals::default()));
+        interpreter.add_observer(Box::new(totals.clone()));
+        interpreter.record_history(true);
+        let blank = interpreter.canvas();
+
+        interpreter.interpret(&moves[..1]).unwrap();
+        let after_cut = (interpreter.canvas(), interpreter.total_cost());
+        interpreter.interpret(&moves[1..3]).unwrap();
+        assert_eq!(interpreter.counter, 2);
+
+        assert!(matches!(interpreter.undo(), Some(Move::Merge { .. })));
+        assert!(matches!(interpreter.undo(), Some(Move::Color { .. })));
+        assert_eq!((interpreter.canvas(), interpreter.total_cost()), after_cut);
+        assert_eq!(interpreter.counter, 1);
+        assert_eq!(interpreter.blocks.len(), 2);
+
+        assert!(matches!(interpreter.redo(), Ok(Some(_))));
+        assert_eq!(interpreter.canvas().pixels[0], Color(255, 0, 0, 255));
+        // Observers see the running total across calls, and the redone move in its place.
+        assert_eq!(totals.borrow().0, vec![(0, 7), (1, 17), (2, 19), (1, 17)]);
+
+        interpreter.undo();
+        interpreter.undo();
+        assert!(interpreter.undo().is_none());
+        assert_eq!(interpreter.canvas(), blank);
+        assert_eq!(interpreter.total_cost(), 0);
+        assert_eq!(interpreter.blocks.len(), 1);
+    }
+
+    #[test]
+    fn checkpoints_can_be_restored() {
+        let (mut interpreter, res) = run("cut [0] [x] [50]");
+        res.unwrap();
+        interpreter.checkpoint("split");
+
+        let moves = parse("color [0.1] [0, 0, 0, 255]\nmerge [0.0] [0.1]");
+        interpreter.interpret(&moves).unwrap();
+
+        interpreter.restore("split").unwrap();
+        assert_eq!(interpreter.checkpoints(), ["split"]);
+        assert_eq!(interpreter.total_cost(), 7);
+        assert_eq!(interpreter.canvas().pixels[99], Color(255, 255, 255, 255));
+        assert!(interpreter
+            .blocks
+            .contains_key(&BlockId::from_path(&[0, 1]).unwrap()));
+        assert!(matches!(
+            interpreter.restore("nope"),
+            Err(InterpreterError::NoSuchCheckpoint(_))
+        ));
+    }
+
+    #[test]
+    fn errors_label_every_block_of_the_move() {
+        let (_, res) = run("cut [0] [x] [50]\nswap [0.0] [0.7]");
+        let err = res.unwrap_err();
+
+        let labels = err
+            .labels()
+            .unwrap()
+            .map(|label| {
+                (
+                    label.offset(),
+                    label.len(),
+                    label.label().unwrap().to_string(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            labels,
+            vec![
+                (17, 4, String::from("refers to a missing block")),
+                (22, 5, String::from("block [0.0]")),
+                (28, 5, String::from("doesn't exist")),
+            ]
+        );
+    }
+
+    #[test]
+    fn check_keeps_the_good_block_of_a_failed_swap() {
+        let (interpreter, moves) = program("cut [0] [x] [50]\nswap [0.0] [0.7]\ncut [0.0] [x] [70]\ncolor [0.0] [0, 0, 0, 255]\nmerge [0.0] [0.1]\ncolor [1] [1, 1, 1, 1]\ncolor [0.7.1] [1, 1, 1, 1]");
+
+        let errors = interpreter.check(&moves);
+        // [0.0] is still checked after the swap, the missing [0.7] isn't reported again.
+        assert_eq!(errors.len(), 2);
+        assert!(matches!(errors[0], InterpreterError::BlockNonExistent(..)));
+        assert!(matches!(errors[1], InterpreterError::OutOfBounds(..)));
+    }
+
+    #[test]
+    fn check_carries_on_past_failed_moves() {
+        let (interpreter, moves) = program("cut [0] [x] [50]\ncolor [0.0] [0, 0, 0, 255]\ncut [0.0] [x] [70]\ncolor [0.0.1] [0, 0, 0, 255]\nmerge [0.0] [0.1]\ncolor [1] [1, 1, 1, 1]\ncolor [2] [1, 1, 1, 1]\ncut [0] [y] [10]");
+
+        let errors = interpreter.check(&moves);
+        // Moves on the bad cut's children aren't reported, the block itself is still merged.
+        assert_eq!(errors.len(), 3);
+        assert!(matches!(errors[0], InterpreterError::OutOfBounds(..)));
+        assert!(matches!(errors[1], InterpreterError::BlockNonExistent(..)));
+        assert!(matches!(errors[2], InterpreterError::BlockNonExistent(..)));
+    }
+}
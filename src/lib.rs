@@ -1,7 +1,16 @@
 pub mod ast;
 pub mod cli;
+pub mod config;
+pub mod cost;
 pub mod error;
+pub mod image;
 pub mod interpreter;
+pub mod observer;
+pub mod optimizer;
 pub mod parser;
+pub mod preview;
+pub mod printer;
+pub mod report;
 pub mod scanner;
 pub mod token;
+pub mod trace;
@@ -1,39 +1,273 @@
-use icfp_isl::cli;
+use icfp_isl::{
+    ast::Color,
+    cli::{self, CanvasConfig, RunOptions},
+    cost::BaseCosts,
+    trace::TraceFormat,
+};
 
 fn main() {
     let mut args = std::env::args();
     args.next();
 
-    match args.next() {
-        Some(command) => match command.as_str() {
+    if let Some(command) = args.next() {
+        match command.as_str() {
             "repl" => {
+                let mut canvas = CanvasConfig::default();
                 let mut verbose = false;
-                if let Some(verbose_str) = args.next() {
-                    verbose = verbose_str == "-v" || verbose_str == "--v";
+
+                while let Some(arg) = args.next() {
+                    match arg.as_str() {
+                        "-v" | "--v" => verbose = true,
+                        _ if canvas_flag(&arg, &mut args, &mut canvas) => (),
+                        _ => panic!("CLI Error: Unknown argument '{arg}' for 'repl'."),
+                    }
                 }
 
-                cli::run_repl(verbose)
+                cli::run_repl(canvas, verbose)
             }
             "run" => {
-                let file_name: String;
+                let (file_name, canvas, options) = run_args(&mut args);
+                cli::run_file(file_name, canvas, options);
+            }
+            "optimize" => {
+                let mut file_name: Option<String> = None;
+                let mut output: Option<String> = None;
+                let mut canvas = CanvasConfig::default();
+                let mut cost_model = BaseCosts::default();
 
-                if let Some(file_name_) = args.next() {
-                    file_name = file_name_;
-                } else {
-                    panic!("CLI Error: No filename supplied for 'run'.")
+                while let Some(arg) = args.next() {
+                    match arg.as_str() {
+                        "-o" | "--output" => match args.next() {
+                            Some(output_) => output = Some(output_),
+                            None => panic!("CLI Error: No output file supplied for '{arg}'."),
+                        },
+                        "-c" | "--cost-model" => {
+                            match args.next().as_deref().and_then(BaseCosts::preset) {
+                                Some(preset) => cost_model = preset,
+                                None => panic!(
+                                "CLI Error: '{arg}' expects a cost model, 'lightning' or 'full'."
+                            ),
+                            }
+                        }
+                        _ if canvas_flag(&arg, &mut args, &mut canvas) => (),
+                        _ => file_name = Some(arg),
+                    }
                 }
 
-                let mut verbose = false;
-                if let Some(verbose_str) = args.next() {
-                    verbose = verbose_str == "-v" || verbose_str == "--v";
+                let file_name = match file_name {
+                    Some(file_name) => file_name,
+                    None => panic!("CLI Error: No filename supplied for 'optimize'."),
+                };
+
+                cli::optimize_file(file_name, canvas, cost_model, output);
+            }
+            "fmt" => {
+                let mut file_name: Option<String> = None;
+                let (mut write, mut check) = (false, false);
+
+                for arg in args.by_ref() {
+                    match arg.as_str() {
+                        // Not `-w`, that's the canvas width everywhere else.
+                        "--write" => write = true,
+                        "--check" => check = true,
+                        _ => file_name = Some(arg),
+                    }
                 }
 
-                cli::run_file(file_name, verbose);
+                let file_name = match file_name {
+                    Some(file_name) => file_name,
+                    None => panic!("CLI Error: No filename supplied for 'fmt'."),
+                };
+
+                if !cli::format_file(file_name, write, check) {
+                    std::process::exit(1);
+                }
+            }
+            "check" => {
+                let mut file_name: Option<String> = None;
+                let mut canvas = CanvasConfig::default();
+
+                while let Some(arg) = args.next() {
+                    match arg.as_str() {
+                        _ if canvas_flag(&arg, &mut args, &mut canvas) => (),
+                        _ => file_name = Some(arg),
+                    }
+                }
+
+                let file_name = match file_name {
+                    Some(file_name) => file_name,
+                    None => panic!("CLI Error: No filename supplied for 'check'."),
+                };
+
+                if !cli::check_file(file_name, canvas) {
+                    std::process::exit(1);
+                }
+            }
+            "render" => {
+                let mut file_name: Option<String> = None;
+                let mut output = String::from("out.png");
+                let mut canvas = CanvasConfig::default();
+                let mut scale = 1;
+
+                while let Some(arg) = args.next() {
+                    match arg.as_str() {
+                        "-o" | "--output" => match args.next() {
+                            Some(output_) => output = output_,
+                            None => panic!("CLI Error: No output file supplied for '{arg}'."),
+                        },
+                        "-s" | "--scale" => match args.next().map(|s| s.parse::<usize>()) {
+                            Some(Ok(scale_)) if scale_ > 0 => scale = scale_,
+                            _ => panic!("CLI Error: '{arg}' expects a positive integer."),
+                        },
+                        _ if canvas_flag(&arg, &mut args, &mut canvas) => (),
+                        _ => file_name = Some(arg),
+                    }
+                }
+
+                let file_name = match file_name {
+                    Some(file_name) => file_name,
+                    None => panic!("CLI Error: No filename supplied for 'render'."),
+                };
+
+                cli::render_file(file_name, canvas, output, scale);
             }
             _ => {
                 panic!("CLI Error: Unknown command.")
             }
+        }
+    }
+}
+
+/// Parses the arguments to `run`, returning the program to run with its canvas and options.
+fn run_args(args: &mut impl Iterator<Item = String>) -> (String, CanvasConfig, RunOptions) {
+    let mut file_name: Option<String> = None;
+    let mut canvas = CanvasConfig::default();
+    let mut options = RunOptions::default();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-v" | "--v" => options.verbose = true,
+            "-t" | "--target" => match args.next() {
+                Some(target) => options.target = Some(target),
+                None => panic!("CLI Error: No target image supplied for '{arg}'."),
+            },
+            "-c" | "--cost-model" => match args.next() {
+                Some(names) => {
+                    for name in names.split(',') {
+                        match BaseCosts::preset(name.trim()) {
+                            Some(preset) => options.cost_models.push(preset),
+                            None => panic!(
+                                "CLI Error: Unknown cost model '{name}', expected 'lightning' or 'full'."
+                            ),
+                        }
+                    }
+                }
+                None => panic!("CLI Error: No cost model supplied for '{arg}'."),
+            },
+            "--preview" => options.preview = true,
+            "--boundaries" => {
+                options.preview = true;
+                options.boundaries = true;
+            }
+            // Not `--width`, that's the canvas width.
+            "--columns" => match args.next().and_then(|columns| columns.parse().ok()) {
+                Some(columns) => options.preview_width = Some(columns),
+                None => panic!("CLI Error: '{arg}' expects a number of columns."),
+            },
+            "--trace" => match args.next() {
+                Some(trace) => options.trace = Some(trace),
+                None => panic!("CLI Error: No trace file supplied for '{arg}'."),
+            },
+            "--trace-format" => match args.next().as_deref().and_then(TraceFormat::from_name) {
+                Some(format) => options.trace_format = Some(format),
+                None => panic!("CLI Error: '{arg}' expects 'csv' or 'jsonl'."),
+            },
+            "-e" | "--export" => match args.next() {
+                Some(export) => options.export = Some(export),
+                None => panic!("CLI Error: No output file supplied for '{arg}'."),
+            },
+            _ if canvas_flag(&arg, args, &mut canvas) => (),
+            _ => file_name = Some(arg),
+        }
+    }
+
+    let file_name = match file_name {
+        Some(file_name) => file_name,
+        None => panic!("CLI Error: No filename supplied for 'run'."),
+    };
+
+    (file_name, canvas, options)
+}
+
+/// Parses the flags describing the starting canvas, returns false for any other argument.
+fn canvas_flag(
+    arg: &str,
+    args: &mut impl Iterator<Item = String>,
+    canvas: &mut CanvasConfig,
+) -> bool {
+    match arg {
+        "-w" | "--width" => match args.next().map(|s| s.parse::<usize>()) {
+            Some(Ok(width)) if width > 0 => canvas.width = width,
+            _ => panic!("CLI Error: '{arg}' expects a positive integer."),
+        },
+        "-h" | "--height" => match args.next().map(|s| s.parse::<usize>()) {
+            Some(Ok(height)) if height > 0 => canvas.height = height,
+            _ => panic!("CLI Error: '{arg}' expects a positive integer."),
+        },
+        "-b" | "--background" => {
+            let rgba = args
+                .next()
+                .map(|s| {
+                    s.split(',')
+                        .map(|c| c.trim().parse::<u8>())
+                        .collect::<Result<Vec<u8>, _>>()
+                })
+                .and_then(Result::ok);
+
+            match rgba.as_deref() {
+                Some(&[r, g, b, a]) => canvas.background = Color(r, g, b, a),
+                _ => panic!("CLI Error: '{arg}' expects a color like '255,255,255,255'."),
+            }
+        }
+        "-i" | "--initial" => match args.next() {
+            Some(initial) => canvas.initial = Some(initial),
+            None => panic!("CLI Error: No initial configuration supplied for '{arg}'."),
+        },
+        "-p" | "--source-png" => match args.next() {
+            Some(source_png) => canvas.source_png = Some(source_png),
+            None => panic!("CLI Error: No source PNG supplied for '{arg}'."),
         },
-        _ => return,
+        _ => return false,
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> impl Iterator<Item = String> + '_ {
+        line.split_whitespace().map(String::from)
+    }
+
+    #[test]
+    fn run_keeps_canvas_and_preview_widths_apart() {
+        let (file_name, canvas, options) = run_args(&mut args(
+            "a.isl --width 200 --height 100 --preview --columns 40",
+        ));
+
+        assert_eq!(file_name, "a.isl");
+        assert_eq!((canvas.width, canvas.height), (200, 100));
+        assert!(options.preview);
+        assert_eq!(options.preview_width, Some(40));
+    }
+
+    #[test]
+    fn run_takes_an_explicit_trace_format() {
+        let (_, _, options) = run_args(&mut args("a.isl --trace out.log --trace-format csv"));
+
+        assert_eq!(options.trace.as_deref(), Some("out.log"));
+        assert_eq!(options.trace_format, Some(TraceFormat::Csv));
     }
 }
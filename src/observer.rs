@@ -0,0 +1,47 @@
+use crate::{
+    ast::{Color, Move},
+    interpreter::{InterpreterError, MoveEffect},
+    report::MoveRecord,
+};
+
+use std::{cell::RefCell, rc::Rc};
+
+/// Everything known about a move once it has been executed.
+#[derive(Debug)]
+pub struct MoveEvent<'a> {
+    /// The index of the move among every move the interpreter has run,
+    /// across calls to `interpret` and counting redone moves.
+    pub index: usize,
+    pub record: &'a MoveRecord,
+    pub effect: &'a MoveEffect,
+    /// The interpreter's running total including this move.
+    pub total_cost: u64,
+    pub width: usize,
+    pub height: usize,
+    /// The canvas after the move, bottom row first.
+    pub canvas: &'a [Color],
+}
+
+/// Hooks into `Interpreter::interpret`, every method does nothing by default.
+pub trait Observer {
+    fn before_move(&mut self, _index: usize, _move_: &Move) {}
+
+    fn after_move(&mut self, _event: &MoveEvent) {}
+
+    fn on_error(&mut self, _index: usize, _move_: &Move, _error: &InterpreterError) {}
+}
+
+/// Lets the caller keep a handle on an observer after registering it.
+impl<T: Observer> Observer for Rc<RefCell<T>> {
+    fn before_move(&mut self, index: usize, move_: &Move) {
+        self.borrow_mut().before_move(index, move_)
+    }
+
+    fn after_move(&mut self, event: &MoveEvent) {
+        self.borrow_mut().after_move(event)
+    }
+
+    fn on_error(&mut self, index: usize, move_: &Move, error: &InterpreterError) {
+        self.borrow_mut().on_error(index, move_, error)
+    }
+}
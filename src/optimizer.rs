@@ -0,0 +1,633 @@
+use crate::{
+    ast::{BlockId, Color, Move, Orientation, Point},
+    cost::MoveKind,
+    interpreter::{BlockData, Interpreter, InterpreterResult, MoveEffect},
+    observer::{MoveEvent, Observer},
+    report::RunReport,
+};
+
+use std::{
+    cell::RefCell,
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
+/// What an optimization pass did to a program.
+#[derive(Debug, Clone)]
+pub struct PassReport {
+    pub moves: Vec<Move>,
+    /// The cost of the program before the pass.
+    pub cost_before: u64,
+    /// The cost of the program after the pass, never more than `cost_before`.
+    pub cost_after: u64,
+}
+
+impl PassReport {
+    pub fn saved(&self) -> u64 {
+        self.cost_before - self.cost_after
+    }
+}
+
+/// An optimization pass, given the program and a way to make a fresh
+/// interpreter in the program's starting state.
+pub type Pass = fn(&[Move], &dyn Fn() -> Interpreter) -> InterpreterResult<PassReport>;
+
+/// Every pass by name, in the order they're best run.
+pub const PASSES: [(&str, Pass); 3] = [
+    ("rewrite-cuts", rewrite_cuts),
+    ("hoist-colors", hoist_colors),
+    ("dead-moves", eliminate_dead_moves),
+];
+
+/// Drops moves that have no effect on the final canvas.
+///
+/// A color move is dead when every pixel it paints is painted over before the
+/// end, following pixels through swaps. A cut is dead when none of the blocks
+/// it makes, or their descendants, are used by a live move.
+pub fn eliminate_dead_moves(
+    moves: &[Move],
+    start: &dyn Fn() -> Interpreter,
+) -> InterpreterResult<PassReport> {
+    let (report, effects) = simulate(moves, start)?;
+    let width = report.canvas.width;
+
+    // The color move that painted each pixel last.
+    let mut painters = vec![None; width * report.canvas.height];
+    for (i, (move_, effect)) in moves.iter().zip(&effects).enumerate() {
+        match move_ {
+            Move::Color { .. } => {
+                for pixel in pixels(&effect.consumed[0].1, width) {
+                    painters[pixel] = Some(i);
+                }
+            }
+            Move::Swap { .. } => {
+                let (shape_1, shape_2) = (&effect.consumed[0].1, &effect.consumed[1].1);
+                for (pixel_1, pixel_2) in pixels(shape_1, width).zip(pixels(shape_2, width)) {
+                    painters.swap(pixel_1, pixel_2);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    let mut visible = vec![false; moves.len()];
+    for painter in painters.into_iter().flatten() {
+        visible[painter] = true;
+    }
+
+    // Walking backwards, `used` holds every block a later live move refers to and their ancestors.
+    let mut used = HashSet::new();
+    let mut live = vec![true; moves.len()];
+    for (i, move_) in moves.iter().enumerate().rev() {
+        let dead = match move_ {
+            Move::Color { .. } => !visible[i],
+            Move::LCut { block_id, .. } => !uses_child(&used, block_id, 2),
+            Move::PCut { block_id, .. } => !uses_child(&used, block_id, 4),
+            Move::Swap { .. } | Move::Merge { .. } => false,
+        };
+
+        if dead {
+            live[i] = false;
+            continue;
+        }
+
+        for block_id in move_.block_ids() {
+            let mut block_id = Some(block_id);
+            while let Some(id) = block_id {
+                used.insert(id.clone());
+                block_id = id.prev.as_deref();
+            }
+        }
+    }
+
+    let optimized = moves
+        .iter()
+        .zip(live)
+        .filter(|(_, live)| *live)
+        .map(|(move_, _)| move_.clone())
+        .collect();
+
+    verify(moves, &report, optimized, start)
+}
+
+/// Colors a cut's parent instead of its children, before the cut.
+///
+/// A cut qualifies when each of its children is next used by a color move, so
+/// nothing sees the pixels they inherit. The color that's most expensive to
+/// paint child by child is painted once on the parent, those children's color
+/// moves are dropped and the other children keep painting over it. This is
+/// repeated, so a color can climb several cuts. Block ids don't change.
+pub fn hoist_colors(
+    moves: &[Move],
+    start: &dyn Fn() -> Interpreter,
+) -> InterpreterResult<PassReport> {
+    let (report, effects) = simulate(moves, start)?;
+
+    // Cuts and merges stay put, so every block keeps the size it was made with.
+    let sizes = effects
+        .iter()
+        .flat_map(|effect| &effect.produced)
+        .map(|(block_id, shape)| (block_id.clone(), shape.size()))
+        .collect::<HashMap<_, _>>();
+
+    let mut optimized = moves.to_vec();
+    while let Some((cut, hoisted, removed)) = find_hoist(&optimized, &sizes) {
+        for i in removed.into_iter().rev() {
+            optimized.remove(i);
+        }
+        optimized.insert(cut, hoisted);
+    }
+
+    verify(moves, &report, optimized, start)
+}
+
+/// Finds the last cut whose color can be hoisted, returning where to put the
+/// parent's color move and the children's color moves it replaces, in order.
+fn find_hoist(moves: &[Move], sizes: &HashMap<BlockId, u64>) -> Option<(usize, Move, Vec<usize>)> {
+    for (cut, move_) in moves.iter().enumerate().rev() {
+        let (block_id, count) = match move_ {
+            Move::LCut { block_id, .. } => (block_id, 2),
+            Move::PCut { block_id, .. } => (block_id, 4),
+            _ => continue,
+        };
+
+        // The color move that next uses each child, if every child has one.
+        let colors = (0..count)
+            .map(|id| {
+                let child = BlockId::new_with_prev(block_id, id);
+                let (i, next) = moves
+                    .iter()
+                    .enumerate()
+                    .skip(cut + 1)
+                    .find(|(_, next)| next.block_ids().contains(&&child))?;
+
+                match next {
+                    Move::Color { color, .. } => Some((i, *color, sizes.get(&child)?)),
+                    _ => None,
+                }
+            })
+            .collect::<Option<Vec<_>>>();
+        let Some(colors) = colors else {
+            continue;
+        };
+
+        // Painting a block costs in inverse proportion to its size.
+        let weight = |color: Color| -> f64 {
+            colors
+                .iter()
+                .filter(|(_, other, _)| *other == color)
+                .map(|(_, _, size)| 1.0 / **size as f64)
+                .sum()
+        };
+        // Ties go to the first child's color.
+        let (first, color, _) = colors.iter().copied().max_by(|(_, lhs, _), (_, rhs, _)| {
+            weight(*lhs)
+                .total_cmp(&weight(*rhs))
+                .then(Ordering::Greater)
+        })?;
+
+        let mut removed = colors
+            .iter()
+            .filter(|(_, other, _)| *other == color)
+            .map(|(i, _, _)| *i)
+            .collect::<Vec<_>>();
+        removed.sort_unstable();
+
+        let hoisted = Move::Color {
+            block_id: block_id.clone(),
+            color,
+            position: moves[first].position().clone(),
+        };
+        return Some((cut, hoisted, removed));
+    }
+
+    None
+}
+
+/// Rewrites each cut into whichever of a point cut or line cuts is cheapest.
+///
+/// A line cut whose halves are next cut at the same place the other way, or
+/// aren't used again, becomes a point cut. A point cut becomes a line cut
+/// followed by line cuts on the halves whose quadrants are used later, across
+/// first or up first. Later block ids are renumbered to the new children.
+pub fn rewrite_cuts(
+    moves: &[Move],
+    start: &dyn Fn() -> Interpreter,
+) -> InterpreterResult<PassReport> {
+    let (report, effects) = simulate(moves, start)?;
+    let interpreter = start();
+    let canvas_size = (report.canvas.width * report.canvas.height) as u64;
+    let cost = |kind, block_size| interpreter.cost_model().cost(kind, canvas_size, block_size);
+
+    // Renames are keyed by the original ids, so the moves are matched up before any are renamed.
+    let mut renames = Renames::default();
+    let mut removed = HashSet::new();
+    let mut optimized = Vec::new();
+    for (i, move_) in moves.iter().enumerate() {
+        if removed.contains(&i) {
+            continue;
+        }
+
+        match move_ {
+            Move::LCut {
+                block_id,
+                orientation,
+                line_no,
+                position,
+            } => {
+                if let Some((point, sub_cuts)) =
+                    point_cut_form(moves, i, block_id, orientation, *line_no)
+                {
+                    let line_cost = cost(MoveKind::LineCut, effects[i].block_size)
+                        + sub_cuts
+                            .iter()
+                            .map(|j| cost(MoveKind::LineCut, effects[*j].block_size))
+                            .sum::<u64>();
+
+                    if cost(MoveKind::PointCut, effects[i].block_size) < line_cost {
+                        let parent = renames.apply(block_id);
+                        // The quadrant each of the halves' children becomes.
+                        let quadrants = match orientation {
+                            Orientation::Vertical => [0, 3, 1, 2],
+                            Orientation::Horizontal => [0, 1, 3, 2],
+                        };
+                        for (path, quadrant) in
+                            [[0, 0], [0, 1], [1, 0], [1, 1]].iter().zip(quadrants)
+                        {
+                            renames.add(child(block_id, path), child(&parent, &[quadrant]));
+                        }
+
+                        optimized.push(Move::PCut {
+                            block_id: parent,
+                            point,
+                            position: position.clone(),
+                        });
+                        removed.extend(sub_cuts);
+                        continue;
+                    }
+                }
+            }
+            Move::PCut {
+                block_id,
+                point,
+                position,
+            } => {
+                let shape = &effects[i].consumed[0].1;
+                let used = |quadrant| {
+                    let path = child(block_id, &[quadrant]).path();
+                    moves[i + 1..]
+                        .iter()
+                        .flat_map(|later| later.block_ids())
+                        .any(|id| id.path().starts_with(&path))
+                };
+                let used = [used(0), used(1), used(2), used(3)];
+                let (width, height) = (shape.tr().x - shape.bl().x, shape.tr().y - shape.bl().y);
+                let (left, bottom) = (point.x - shape.bl().x, point.y - shape.bl().y);
+
+                // Each half's quadrants in line cut order, and the half's size.
+                let across = [([0, 3], left * height), ([1, 2], (width - left) * height)];
+                let up = [
+                    ([0, 1], bottom * width),
+                    ([3, 2], (height - bottom) * width),
+                ];
+                let line_cost = |halves: &[([u64; 2], u64); 2]| {
+                    halves
+                        .iter()
+                        .filter(|(quadrants, _)| quadrants.iter().any(|q| used[*q as usize]))
+                        .map(|(_, size)| cost(MoveKind::LineCut, *size))
+                        .sum::<u64>()
+                        + cost(MoveKind::LineCut, shape.size())
+                };
+
+                let (orientation, halves) = if line_cost(&up) < line_cost(&across) {
+                    (Orientation::Horizontal, up)
+                } else {
+                    (Orientation::Vertical, across)
+                };
+
+                if line_cost(&halves) < cost(MoveKind::PointCut, shape.size()) {
+                    let parent = renames.apply(block_id);
+                    let (line_no, sub_line_no, sub_orientation) = match orientation {
+                        Orientation::Vertical => (point.x, point.y, Orientation::Horizontal),
+                        Orientation::Horizontal => (point.y, point.x, Orientation::Vertical),
+                    };
+
+                    optimized.push(Move::LCut {
+                        block_id: parent.clone(),
+                        orientation,
+                        line_no,
+                        position: position.clone(),
+                    });
+                    for (half, (quadrants, _)) in halves.iter().enumerate() {
+                        if !quadrants.iter().any(|q| used[*q as usize]) {
+                            continue;
+                        }
+
+                        let half = child(&parent, &[half as u64]);
+                        for (id, quadrant) in quadrants.iter().enumerate() {
+                            renames.add(child(block_id, &[*quadrant]), child(&half, &[id as u64]));
+                        }
+                        optimized.push(Move::LCut {
+                            block_id: half,
+                            orientation: sub_orientation.clone(),
+                            line_no: sub_line_no,
+                            position: position.clone(),
+                        });
+                    }
+                    continue;
+                }
+            }
+            _ => (),
+        }
+
+        optimized.push(move_.map_block_ids(|id| renames.apply(id)));
+    }
+
+    verify(moves, &report, optimized, start)
+}
+
+/// The point a line cut and the cuts on its halves make up together, with
+/// the indices of those cuts.
+///
+/// Each half has to be either cut next, the other way at the same place, or
+/// never used again, and at least one of them has to be cut.
+fn point_cut_form(
+    moves: &[Move],
+    cut: usize,
+    block_id: &BlockId,
+    orientation: &Orientation,
+    line_no: u64,
+) -> Option<(Point, Vec<usize>)> {
+    let mut sub_cuts = Vec::new();
+    let mut sub_line_no = None;
+
+    for half in 0..2 {
+        let half = child(block_id, &[half]);
+        let Some((j, next)) = moves
+            .iter()
+            .enumerate()
+            .skip(cut + 1)
+            .find(|(_, next)| next.block_ids().contains(&&half))
+        else {
+            continue;
+        };
+
+        match next {
+            Move::LCut {
+                block_id,
+                orientation: sub_orientation,
+                line_no,
+                ..
+            } if *block_id == half
+                && sub_orientation != orientation
+                && sub_line_no.is_none_or(|other| other == *line_no) =>
+            {
+                sub_line_no = Some(*line_no);
+                sub_cuts.push(j);
+            }
+            _ => return None,
+        }
+    }
+
+    let point = match orientation {
+        Orientation::Vertical => Point {
+            x: line_no,
+            y: sub_line_no?,
+        },
+        Orientation::Horizontal => Point {
+            x: sub_line_no?,
+            y: line_no,
+        },
+    };
+    Some((point, sub_cuts))
+}
+
+/// Maps original block ids, and everything cut from them, to their new ids.
+#[derive(Debug, Default)]
+struct Renames(Vec<(Vec<u64>, Vec<u64>)>);
+
+impl Renames {
+    fn add(&mut self, from: BlockId, to: BlockId) {
+        self.0.push((from.path(), to.path()));
+    }
+
+    /// Renames by the longest original id the block id starts with.
+    fn apply(&self, block_id: &BlockId) -> BlockId {
+        let path = block_id.path();
+        let Some((from, to)) = self
+            .0
+            .iter()
+            .filter(|(from, _)| path.starts_with(from))
+            .max_by_key(|(from, _)| from.len())
+        else {
+            return block_id.clone();
+        };
+
+        let renamed = to
+            .iter()
+            .chain(&path[from.len()..])
+            .copied()
+            .collect::<Vec<_>>();
+        BlockId::from_path(&renamed).unwrap_or_else(|| block_id.clone())
+    }
+}
+
+fn child(block_id: &BlockId, path: &[u64]) -> BlockId {
+    path.iter().fold(block_id.clone(), |id, child| {
+        BlockId::new_with_prev(&id, *child)
+    })
+}
+
+/// Records the effect of every move.
+#[derive(Debug, Default)]
+struct EffectLog(Vec<MoveEffect>);
+
+impl Observer for EffectLog {
+    fn after_move(&mut self, event: &MoveEvent) {
+        self.0.push(event.effect.clone());
+    }
+}
+
+/// Runs the program, returning its report and the effect of each move.
+fn simulate(
+    moves: &[Move],
+    start: &dyn Fn() -> Interpreter,
+) -> InterpreterResult<(RunReport, Vec<MoveEffect>)> {
+    let log = Rc::new(RefCell::new(EffectLog::default()));
+    let mut interpreter = start();
+    interpreter.add_observer(Box::new(log.clone()));
+
+    let report = interpreter.interpret(moves)?;
+    let effects = log.take().0;
+    Ok((report, effects))
+}
+
+/// Keeps the optimized program only if it paints the same canvas for less.
+fn verify(
+    moves: &[Move],
+    report: &RunReport,
+    optimized: Vec<Move>,
+    start: &dyn Fn() -> Interpreter,
+) -> InterpreterResult<PassReport> {
+    let unchanged = PassReport {
+        moves: moves.to_vec(),
+        cost_before: report.total_cost,
+        cost_after: report.total_cost,
+    };
+
+    match start().interpret(&optimized) {
+        Ok(new) if new.canvas == report.canvas && new.total_cost < report.total_cost => {
+            Ok(PassReport {
+                moves: optimized,
+                cost_before: report.total_cost,
+                cost_after: new.total_cost,
+            })
+        }
+        _ => Ok(unchanged),
+    }
+}
+
+/// Whether any of the first `count` children of `block_id` is used.
+fn uses_child(used: &HashSet<BlockId>, block_id: &BlockId, count: u64) -> bool {
+    (0..count).any(|id| used.contains(&BlockId::new_with_prev(block_id, id)))
+}
+
+/// The canvas indices of the pixels under `shape`, bottom row first.
+fn pixels(shape: &BlockData, width: usize) -> impl Iterator<Item = usize> {
+    let (bl, tr) = (shape.bl(), shape.tr());
+    (bl.y..tr.y).flat_map(move |y| (bl.x..tr.x).map(move |x| x as usize + y as usize * width))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cost::BaseCosts,
+        interpreter::tests::{blank, parse},
+    };
+
+    #[test]
+    fn drops_overwritten_colors_and_unused_cuts() {
+        let moves = parse(
+            "color [0] [1, 1, 1, 255]\n\
+             cut [0] [x] [50]\n\
+             color [0.0] [2, 2, 2, 255]\n\
+             color [0.1] [3, 3, 3, 255]\n\
+             color [0.0] [4, 4, 4, 255]\n\
+             cut [0.1] [y] [50]",
+        );
+        let report = eliminate_dead_moves(&moves, &blank).unwrap();
+
+        assert_eq!(
+            report.moves,
+            parse("cut [0] [x] [50]\ncolor [0.1] [3, 3, 3, 255]\ncolor [0.0] [4, 4, 4, 255]")
+        );
+        assert!(report.cost_after < report.cost_before);
+        assert_eq!(
+            blank().interpret(&report.moves).unwrap().canvas,
+            blank().interpret(&moves).unwrap().canvas
+        );
+    }
+
+    #[test]
+    fn follows_pixels_through_swaps() {
+        // The swap moves the painted pixels right, so painting the left half again keeps them.
+        let moves = parse(
+            "cut [0] [x] [50]\n\
+             color [0.0] [1, 1, 1, 255]\n\
+             swap [0.0] [0.1]\n\
+             color [0.1] [2, 2, 2, 255]",
+        );
+        let report = eliminate_dead_moves(&moves, &blank).unwrap();
+
+        assert_eq!(report.moves, moves);
+        assert_eq!(report.saved(), 0);
+    }
+
+    #[test]
+    fn hoists_shared_colors_up_the_cuts() {
+        let moves = parse(
+            "cut [0] [x] [50]\n\
+             cut [0.0] [25, 50]\n\
+             color [0.1] [9, 9, 9, 255]\n\
+             color [0.0.0] [9, 9, 9, 255]\n\
+             color [0.0.1] [9, 9, 9, 255]\n\
+             color [0.0.2] [9, 9, 9, 255]\n\
+             color [0.0.3] [1, 1, 1, 255]",
+        );
+        let report = hoist_colors(&moves, &blank).unwrap();
+
+        assert_eq!(
+            report.moves,
+            parse(
+                "color [0] [9, 9, 9, 255]\n\
+                 cut [0] [x] [50]\n\
+                 cut [0.0] [25, 50]\n\
+                 color [0.0.3] [1, 1, 1, 255]"
+            )
+        );
+        // 7 + 20 + 10 + 4 * 40 = 197 before, 5 + 7 + 20 + 40 = 72 after.
+        assert_eq!((report.cost_before, report.cost_after), (197, 72));
+    }
+
+    #[test]
+    fn joins_line_cuts_into_a_point_cut() {
+        let moves = parse(
+            "cut [0] [y] [30]\n\
+             cut [0.0] [x] [40]\n\
+             cut [0.1] [x] [40]\n\
+             color [0.1.0] [1, 1, 1, 255]\n\
+             cut [0.0.1] [y] [10]\n\
+             color [0.0.1.1] [2, 2, 2, 255]",
+        );
+        let report = rewrite_cuts(&moves, &blank).unwrap();
+
+        assert_eq!(
+            report.moves,
+            parse(
+                "cut [0] [40, 30]\n\
+                 color [0.3] [1, 1, 1, 255]\n\
+                 cut [0.1] [y] [10]\n\
+                 color [0.1.1] [2, 2, 2, 255]"
+            )
+        );
+        assert!(report.cost_after < report.cost_before);
+    }
+
+    #[test]
+    fn splits_point_cuts_when_line_cuts_are_cheaper() {
+        let start = || {
+            let mut interpreter = blank();
+            interpreter.set_cost_model(Box::new(BaseCosts {
+                name: "cheap-lines",
+                line_cut: 1,
+                ..BaseCosts::LIGHTNING
+            }));
+            interpreter
+        };
+        // Only the left quadrants are used, so the right half is never cut.
+        let moves = parse("cut [0] [50, 50]\ncolor [0.0] [1, 1, 1, 255]\ncut [0.3] [x] [10]");
+        let report = rewrite_cuts(&moves, &start).unwrap();
+
+        assert_eq!(
+            report.moves,
+            parse(
+                "cut [0] [x] [50]\n\
+                 cut [0.0] [y] [50]\n\
+                 color [0.0.0] [1, 1, 1, 255]\n\
+                 cut [0.0.1] [x] [10]"
+            )
+        );
+        // 10 + 20 + 4 = 34 before, 1 + 2 + 20 + 4 = 27 after.
+        assert_eq!((report.cost_before, report.cost_after), (34, 27));
+    }
+
+    #[test]
+    fn leaves_children_that_keep_the_parent_pixels() {
+        let moves = parse("cut [0] [x] [50]\ncolor [0.0] [9, 9, 9, 255]");
+        let report = hoist_colors(&moves, &blank).unwrap();
+
+        assert_eq!(report.moves, moves);
+    }
+}
@@ -1,9 +1,9 @@
 use crate::{
-    ast::{BlockId, Color, Move, Orientation, Point},
+    ast::{BlockId, Color, Move, Orientation, Point, Statement},
     token::{Position, Token, TokenType},
 };
 
-use miette::Diagnostic;
+use miette::{Diagnostic, LabeledSpan, SourceCode};
 use thiserror::Error;
 
 #[derive(Debug)]
@@ -13,7 +13,9 @@ pub struct Parser {
 }
 
 impl Parser {
-    fn new(source: &[Token]) -> Parser {
+    /// A parser over scanned tokens, `parse` then returns the moves that
+    /// parsed together with every syntax error.
+    pub fn new(source: &[Token]) -> Parser {
         Parser {
             source: source.to_vec(),
             current: 0,
@@ -21,7 +23,9 @@ impl Parser {
     }
 
     pub fn is_at_end(&self) -> bool {
-        self.current > self.source.len() - 2
+        self.source
+            .get(self.current)
+            .is_none_or(|tok| tok.token_type == TokenType::Eof)
     }
 
     fn increment_current(&mut self) {
@@ -32,7 +36,8 @@ impl Parser {
         if let Some(tok) = self.peek() {
             tok.position
         } else {
-            self.peek().unwrap().position
+            // Past the last line, point at the end of the file.
+            self.source[self.source.len() - 1].position.clone()
         }
     }
 
@@ -47,7 +52,7 @@ impl Parser {
 
     /// Returns the next token.
     fn peek_twice(&self) -> Option<Token> {
-        if self.current < self.source.len() - 2 {
+        if self.current + 2 < self.source.len() {
             Some(self.source[self.current + 1].to_owned())
         } else {
             None
@@ -109,34 +114,114 @@ impl Parser {
         val
     }
 
-    pub fn parse_tokens(source: &[Token]) -> ParserResult<Vec<Move>> {
-        let mut parsy = Parser::new(source);
-        parsy.parse()
+    /// Parses every move, failing with all the syntax errors if there are any.
+    ///
+    /// Use `Parser::new(source).parse()` to keep the moves that parsed as well.
+    pub fn parse_tokens(source: &[Token]) -> Result<Vec<Move>, Vec<ParserError>> {
+        let (moves, errors) = Parser::new(source).parse();
+
+        if errors.is_empty() {
+            Ok(moves)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Parses every move and comment, failing with all the syntax errors if there are any.
+    ///
+    /// Use `Parser::new(source).statements()` to keep the statements that parsed as well.
+    pub fn parse_statements(source: &[Token]) -> Result<Vec<Statement>, Vec<ParserError>> {
+        let (statements, errors) = Parser::new(source).program();
+
+        if errors.is_empty() {
+            Ok(statements)
+        } else {
+            Err(errors)
+        }
     }
 
     // ! ENTRY POINT
-    pub fn parse(&mut self) -> ParserResult<Vec<Move>> {
+    /// Returns the moves that parsed and the errors on the lines that didn't.
+    pub fn parse(&mut self) -> (Vec<Move>, Vec<ParserError>) {
+        let (statements, errors) = self.program();
+        let moves = statements
+            .into_iter()
+            .filter_map(|statement| match statement {
+                Statement::Move(move_, _) => Some(move_),
+                Statement::Comment(_) | Statement::Blank => None,
+            })
+            .collect();
+
+        (moves, errors)
+    }
+
+    /// Returns the statements that parsed and the errors on the lines that didn't.
+    pub fn statements(&mut self) -> (Vec<Statement>, Vec<ParserError>) {
         self.program()
     }
 
     /// program -> program-line | program-line newline program
-    fn program(&mut self) -> ParserResult<Vec<Move>> {
-        let mut moves = Vec::new();
+    fn program(&mut self) -> (Vec<Statement>, Vec<ParserError>) {
+        let mut statements = Vec::new();
+        let mut errors = Vec::new();
+
         while !self.is_at_end() {
-            moves.push(self.program_line()?);
+            // A run of blank lines is kept as one.
+            if self.matches(&[TokenType::NewLine]) {
+                if statements.last() != Some(&Statement::Blank) {
+                    statements.push(Statement::Blank);
+                }
+                continue;
+            }
+
+            if let Some(text) = self.comment() {
+                statements.push(Statement::Comment(text));
+                self.matches(&[TokenType::NewLine]);
+                continue;
+            }
+
+            match self.program_line() {
+                Ok((move_, comment)) => statements.push(Statement::Move(move_, comment)),
+                Err(e) => {
+                    errors.push(e);
+                    self.synchronize();
+                }
+            }
+        }
+
+        (statements, errors)
+    }
+
+    /// comment -> "#" unicode-string
+    fn comment(&mut self) -> Option<String> {
+        if !self.check(TokenType::Comment) {
+            return None;
         }
 
-        Ok(moves)
+        let tok = self.advance()?;
+        let (start, end) = tok.position.indices;
+        Some(tok.position.src[start + 1..end].trim_end().to_string())
+    }
+
+    /// Skips the rest of a bad line, picking up again at the next one.
+    fn synchronize(&mut self) {
+        while let Some(tok) = self.advance() {
+            if tok.token_type == TokenType::NewLine {
+                break;
+            }
+        }
     }
 
-    fn program_line(&mut self) -> ParserResult<Move> {
+    /// program-line -> move [comment]
+    fn program_line(&mut self) -> ParserResult<(Move, Option<String>)> {
         let move_ = self.move_()?;
+        let comment = self.comment();
 
         if self.check(TokenType::NewLine) {
             self.consume(TokenType::NewLine, "Expected newline after move")?;
         }
 
-        Ok(move_)
+        Ok((move_, comment))
     }
 
     /// move -> cut-move
@@ -170,8 +255,8 @@ impl Parser {
     /// cut-move -> pcut-move | lcut-move ;
     fn cut_move(&mut self) -> ParserResult<Move> {
         let tok = self.consume(TokenType::Cut, "")?;
-        let position = tok.position;
-        let block_id = self.block()?;
+        let mut position = tok.position;
+        let block_id = self.block(&mut position)?;
 
         // pcut-move -> "cut" block point
         let move_ = if self.check_twice(TokenType::Number) {
@@ -202,39 +287,42 @@ impl Parser {
     /// <color-move> -> "color" block color ;
     fn color_move(&mut self) -> ParserResult<Move> {
         let tok = self.consume(TokenType::Color, "")?;
-        let block_id = self.block()?;
+        let mut position = tok.position;
+        let block_id = self.block(&mut position)?;
         let color = self.color()?;
 
         Ok(Move::Color {
             block_id,
             color,
-            position: tok.position,
+            position,
         })
     }
 
     /// swap-move -> "swap" block block ;
     fn swap_move(&mut self) -> ParserResult<Move> {
         let tok = self.consume(TokenType::Swap, "")?;
-        let block_id_1 = self.block()?;
-        let block_id_2 = self.block()?;
+        let mut position = tok.position;
+        let block_id_1 = self.block(&mut position)?;
+        let block_id_2 = self.block(&mut position)?;
 
         Ok(Move::Swap {
             block_id_1,
             block_id_2,
-            position: tok.position,
+            position,
         })
     }
 
     /// merge-move -> "merge" block block ;
     fn merge_move(&mut self) -> ParserResult<Move> {
         let tok = self.consume(TokenType::Merge, "")?;
-        let block_id_1 = self.block()?;
-        let block_id_2 = self.block()?;
+        let mut position = tok.position;
+        let block_id_1 = self.block(&mut position)?;
+        let block_id_2 = self.block(&mut position)?;
 
         Ok(Move::Merge {
             block_id_1,
             block_id_2,
-            position: tok.position,
+            position,
         })
     }
 
@@ -257,18 +345,19 @@ impl Parser {
     /// <vertical> ::= "X" | "x"
     /// <horizontal> ::= "Y" | "y"
     fn orientation_type(&mut self) -> ParserResult<Orientation> {
-        if let Some(tok) = self.advance() {
-            match tok.token_type {
-                TokenType::Horizontal => Ok(Orientation::Horizontal),
-                TokenType::Vertical => Ok(Orientation::Vertical),
-                _ => Err(expected(
-                    &[TokenType::Vertical, TokenType::Horizontal],
-                    "Expected an orientation type",
-                    tok.position,
-                )),
-            }
-        } else {
+        if self.matches(&[TokenType::Horizontal]) {
+            Ok(Orientation::Horizontal)
+        } else if self.matches(&[TokenType::Vertical]) {
+            Ok(Orientation::Vertical)
+        } else if self.is_at_end() {
             Err(ParserError::Eof(self.get_curr_position()))
+        } else {
+            // Anything else, a newline included, is left for `synchronize`.
+            Err(expected(
+                &[TokenType::Vertical, TokenType::Horizontal],
+                "Expected an orientation type",
+                self.get_curr_position(),
+            ))
         }
     }
 
@@ -350,17 +439,23 @@ impl Parser {
     }
 
     /// <block> ::= "[" <block-id> "]"
-    fn block(&mut self) -> ParserResult<BlockId> {
-        self.consume(
+    ///
+    /// Records where the block is written on the position of the move's name.
+    fn block(&mut self, move_position: &mut Position) -> ParserResult<BlockId> {
+        let open = self.consume(
             TokenType::LeftBrace,
             "Expected a '[' at the beginning of a block",
         )?;
         let id = self.block_id()?;
-        self.consume(
+        let close = self.consume(
             TokenType::RightBrace,
             "Expected a ']' at the end of a block",
         )?;
 
+        let (start, end) = (open.position.indices.0, close.position.indices.1);
+        move_position
+            .blocks
+            .push((id.clone(), (start, end - start).into()));
         Ok(id)
     }
 
@@ -389,9 +484,8 @@ impl Parser {
     }
 }
 
-#[derive(Debug, Diagnostic, Error)]
+#[derive(Debug, Error)]
 pub enum ParserError {
-    #[diagnostic(code(parser::Parser))]
     #[error("{1}, {2}.")]
     Expected(TokenType, String, Position),
     #[error("{reason}, {position}.")]
@@ -400,12 +494,51 @@ pub enum ParserError {
         reason: String,
         position: Position,
     },
-    #[error("{0}, at {1:?}")]
+    #[error("{0} is too big for a color channel, {1}.")]
     TooBigForRGBA(u64, Position),
-    #[error("At the end of the file, at {0:?}.")]
+    #[error("Reached the end of the file, {0}.")]
     Eof(Position),
 }
 
+impl ParserError {
+    pub fn position(&self) -> &Position {
+        match self {
+            ParserError::Expected(_, _, position)
+            | ParserError::ExpectedOneOf { position, .. }
+            | ParserError::TooBigForRGBA(_, position)
+            | ParserError::Eof(position) => position,
+        }
+    }
+}
+
+impl Diagnostic for ParserError {
+    fn code<'a>(&'a self) -> Option<Box<dyn std::fmt::Display + 'a>> {
+        Some(Box::new("isl::parser"))
+    }
+
+    fn source_code(&self) -> Option<&dyn SourceCode> {
+        Some(self.position().src.as_ref())
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = LabeledSpan> + '_>> {
+        let label = match self {
+            ParserError::Expected(token_type, _, _) => format!("expected {token_type}"),
+            ParserError::ExpectedOneOf { token_types, .. } => {
+                let names = token_types
+                    .iter()
+                    .map(|token_type| token_type.to_string())
+                    .collect::<Vec<_>>();
+                format!("expected one of {}", names.join(", "))
+            }
+            ParserError::TooBigForRGBA(_, _) => String::from("must be from 0 to 255"),
+            ParserError::Eof(_) => String::from("the file ends here"),
+        };
+
+        let span = LabeledSpan::new_with_span(Some(label), self.position().span());
+        Some(Box::new(std::iter::once(span)))
+    }
+}
+
 fn expected(token_types: &[TokenType], reason: &str, position: Position) -> ParserError {
     if token_types.len() == 1 {
         ParserError::Expected(token_types[0].clone(), reason.to_string(), position)
@@ -426,7 +559,7 @@ mod tests {
     use crate::scanner::Scanner;
 
     fn can_parse(src: &str, verbose: bool) {
-        let tokens = Scanner::scan_str(src);
+        let tokens = Scanner::scan_str(src).unwrap();
         if verbose {
             tokens
                 .iter()
@@ -434,11 +567,11 @@ mod tests {
                 .for_each(|(i, tok)| println!("[{i}] :-> {tok:?}"))
         }
         let mut parsy = Parser::new(&tokens);
-        let res = parsy.parse();
+        let (moves, errors) = parsy.parse();
 
-        match res {
-            Ok(yay) => println!("SUCCESS: {yay:?}"),
-            Err(neh) => panic!("FAILURE, MOTHER RUSSIA IS DISAPPOINTED:\n{neh}"),
+        match errors.first() {
+            None => println!("SUCCESS: {moves:?}"),
+            Some(neh) => panic!("FAILURE, MOTHER RUSSIA IS DISAPPOINTED:\n{neh}"),
         }
     }
 
@@ -466,4 +599,33 @@ mod tests {
     fn can_parse_merge_move() {
         can_parse("merge [69] [96]", false)
     }
+
+    #[test]
+    fn recovers_at_the_next_line() {
+        let src = "cut [0] [x] [50]\ncut [0.0] [x]\n\ncolor [0.1] [1, 2, 3]\nswap [0.0] [0.1]\nmerge [0.0]";
+        let tokens = Scanner::scan_str(src).unwrap();
+        let (moves, errors) = Parser::new(&tokens).parse();
+
+        assert_eq!(moves.len(), 2);
+        assert!(matches!(moves[1], Move::Swap { .. }));
+
+        let lines = errors.iter().map(|e| e.position().line).collect::<Vec<_>>();
+        assert_eq!(lines, vec![1, 3, 5]);
+
+        // A line cut short before its orientation doesn't take the next line with it.
+        let tokens = Scanner::scan_str("cut [0] [\ncolor [0] [1, 2, 3, 4]").unwrap();
+        let (moves, errors) = Parser::new(&tokens).parse();
+        assert_eq!(moves.len(), 1);
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn parses_empty_programs() {
+        for src in ["", "  \n\n\t\n"] {
+            let tokens = Scanner::scan_str(src).unwrap();
+            let (moves, errors) = Parser::new(&tokens).parse();
+            assert!(moves.is_empty());
+            assert!(errors.is_empty());
+        }
+    }
 }
@@ -0,0 +1,214 @@
+use crate::{ast::Color, image::Image, interpreter::BlockData};
+
+use colored::Colorize;
+
+/// The color block boundaries are drawn in.
+const BOUNDARY: Color = Color(255, 0, 255, 255);
+
+/// The width of the terminal on stdout, falling back to `$COLUMNS` when stdout
+/// isn't a terminal and to 80 columns when that isn't set either.
+pub fn terminal_width() -> usize {
+    window_columns()
+        .or_else(|| {
+            std::env::var("COLUMNS")
+                .ok()
+                .and_then(|columns| columns.parse().ok())
+        })
+        .filter(|columns| *columns > 0)
+        .unwrap_or(80)
+}
+
+/// Asks the terminal on stdout for its size.
+#[cfg(unix)]
+fn window_columns() -> Option<usize> {
+    let mut size = libc::winsize {
+        ws_row: 0,
+        ws_col: 0,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    // SAFETY: TIOCGWINSZ only writes a `winsize` through the pointer, which lives across the call.
+    let result = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+
+    (result == 0 && size.ws_col > 0).then_some(size.ws_col as usize)
+}
+
+#[cfg(not(unix))]
+fn window_columns() -> Option<usize> {
+    None
+}
+
+/// Renders the image with true-color half blocks, two pixel rows per line of text.
+///
+/// The image is shrunk to fit in `max_columns`, and the outline of every
+/// block in `boundaries` is drawn on top when it's given.
+pub fn render(image: &Image, boundaries: Option<&[BlockData]>, max_columns: usize) -> String {
+    let preview = Preview::sample(image, boundaries, max_columns);
+    let mut out = String::new();
+
+    for top in (0..preview.rows).step_by(2) {
+        for column in 0..preview.columns {
+            let Color(r, g, b, _) = over_white(preview.cell(column, top));
+            let half = "▀".truecolor(r, g, b);
+
+            let half = if top + 1 < preview.rows {
+                let Color(r, g, b, _) = over_white(preview.cell(column, top + 1));
+                half.on_truecolor(r, g, b)
+            } else {
+                half
+            };
+            out.push_str(&half.to_string());
+        }
+        out.push('\n');
+    }
+
+    out
+}
+
+/// The image shrunk to a grid of cells, top row first.
+#[derive(Debug)]
+struct Preview {
+    columns: usize,
+    rows: usize,
+    cells: Vec<Color>,
+}
+
+impl Preview {
+    /// Averages every `scale`x`scale` square of pixels into a cell, the fewest
+    /// squares needed to fit in `max_columns`.
+    fn sample(image: &Image, boundaries: Option<&[BlockData]>, max_columns: usize) -> Preview {
+        let scale = image.width.div_ceil(max_columns.max(1)).max(1);
+        let columns = image.width.div_ceil(scale);
+        let rows = image.height.div_ceil(scale);
+        let edges = boundaries.map(|shapes| edge_mask(image, shapes));
+
+        let mut cells = Vec::with_capacity(columns * rows);
+        for row in 0..rows {
+            // Rows are counted from the top, the image from the bottom.
+            let y_end = image.height - row * scale;
+            let y_start = y_end.saturating_sub(scale);
+
+            for column in 0..columns {
+                let x_start = column * scale;
+                let x_end = (x_start + scale).min(image.width);
+
+                let indices = (y_start..y_end)
+                    .flat_map(|y| (x_start..x_end).map(move |x| x + y * image.width))
+                    .collect::<Vec<_>>();
+
+                let on_edge = edges
+                    .as_ref()
+                    .is_some_and(|edges| indices.iter().any(|i| edges[*i]));
+
+                if on_edge {
+                    cells.push(BOUNDARY);
+                } else {
+                    cells.push(average(indices.iter().map(|i| image.pixels[*i])));
+                }
+            }
+        }
+
+        Preview {
+            columns,
+            rows,
+            cells,
+        }
+    }
+
+    fn cell(&self, column: usize, row: usize) -> Color {
+        self.cells[column + row * self.columns]
+    }
+}
+
+/// Marks the pixels along the inside edge of every shape.
+fn edge_mask(image: &Image, shapes: &[BlockData]) -> Vec<bool> {
+    let mut edges = vec![false; image.width * image.height];
+    let mut mark = |x: u64, y: u64| {
+        let (x, y) = (x as usize, y as usize);
+        if x < image.width && y < image.height {
+            edges[x + y * image.width] = true;
+        }
+    };
+
+    for shape in shapes {
+        let (bl, tr) = (shape.bl(), shape.tr());
+        if bl.x >= tr.x || bl.y >= tr.y {
+            continue;
+        }
+
+        for x in bl.x..tr.x {
+            mark(x, bl.y);
+            mark(x, tr.y - 1);
+        }
+        for y in bl.y..tr.y {
+            mark(bl.x, y);
+            mark(tr.x - 1, y);
+        }
+    }
+
+    edges
+}
+
+fn average(colors: impl Iterator<Item = Color>) -> Color {
+    let mut sum = [0u64; 4];
+    let mut count = 0;
+    for Color(r, g, b, a) in colors {
+        for (total, channel) in sum.iter_mut().zip([r, g, b, a]) {
+            *total += channel as u64;
+        }
+        count += 1;
+    }
+
+    let channel = |total: u64| (total as f64 / count.max(1) as f64).round() as u8;
+    Color(
+        channel(sum[0]),
+        channel(sum[1]),
+        channel(sum[2]),
+        channel(sum[3]),
+    )
+}
+
+/// Blends a translucent color over white, the way a blank canvas looks.
+fn over_white(Color(r, g, b, a): Color) -> Color {
+    let blend = |channel: u8| {
+        let alpha = a as f64 / 255.0;
+        (channel as f64 * alpha + 255.0 * (1.0 - alpha)).round() as u8
+    };
+    Color(blend(r), blend(g), blend(b), 255)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Point;
+
+    const WHITE: Color = Color(255, 255, 255, 255);
+    const BLACK: Color = Color(0, 0, 0, 255);
+
+    #[test]
+    fn shrinks_to_fit_and_flips_rows() {
+        // The bottom half is black, the top half white.
+        let mut image = Image::new(100, 40, vec![WHITE; 100 * 40]);
+        for pixel in &mut image.pixels[..100 * 20] {
+            *pixel = BLACK;
+        }
+
+        let preview = Preview::sample(&image, None, 30);
+        assert_eq!((preview.columns, preview.rows), (25, 10));
+        assert_eq!(preview.cell(0, 0), WHITE);
+        assert_eq!(preview.cell(24, 9), BLACK);
+        assert_eq!(render(&image, None, 30).lines().count(), 5);
+    }
+
+    #[test]
+    fn draws_block_boundaries() {
+        let image = Image::new(10, 10, vec![WHITE; 100]);
+        let shape = BlockData::from_corners(Point { x: 0, y: 0 }, Point { x: 5, y: 10 });
+
+        let preview = Preview::sample(&image, Some(&[shape]), 10);
+        assert_eq!(preview.cell(0, 3), BOUNDARY);
+        assert_eq!(preview.cell(4, 3), BOUNDARY);
+        assert_eq!(preview.cell(2, 3), WHITE);
+        assert_eq!(preview.cell(7, 3), WHITE);
+    }
+}
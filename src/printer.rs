@@ -0,0 +1,64 @@
+use crate::{
+    ast::{Move, Statement},
+    error::ISLResult,
+    parser::Parser,
+    scanner::Scanner,
+};
+
+/// Prints a program in canonical ISL, one statement per line.
+///
+/// Moves are printed with single spaces between their parts and no spaces
+/// inside brackets, so parsing the output gives back the same program.
+pub fn print(statements: &[Statement]) -> String {
+    statements
+        .iter()
+        .map(|statement| format!("{statement}\n"))
+        .collect()
+}
+
+/// Prints moves in canonical ISL, one per line.
+pub fn print_moves(moves: &[Move]) -> String {
+    moves.iter().map(|move_| format!("{move_}\n")).collect()
+}
+
+/// Reformats ISL source, keeping its comments where they are and its blank
+/// lines, a run of them as one.
+pub fn format_source(src: &str) -> ISLResult<String> {
+    let tokens = Scanner::scan_str(src)?;
+    let statements = Parser::parse_statements(&tokens)?;
+    Ok(print(&statements))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn statements(src: &str) -> Vec<Statement> {
+        Parser::parse_statements(&Scanner::scan_str(src).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn prints_canonical_isl() {
+        let src = "# split it\ncut  [0]  [ 50 , 50 ]\n\n\n\ncolor [0.1] [ 1, 2,3 , 4]  # paint  \ncut [0.0] [x] [10]\nswap [0.2] [0.3]\nmerge [0.2] [0.3]\n";
+
+        assert_eq!(
+            format_source(src).unwrap(),
+            "# split it\ncut [0] [50,50]\n\ncolor [0.1] [1,2,3,4] # paint\ncut [0.0] [X] [10]\nswap [0.2] [0.3]\nmerge [0.2] [0.3]\n"
+        );
+    }
+
+    #[test]
+    fn printing_round_trips() {
+        for src in [
+            include_str!("../examples/big_boy.isl"),
+            "",
+            "#no space\n#   indented  \n\ncut [1.2.3] [y] [7]#tight\ncolor [4] [0,0,0,0]\n\n",
+        ] {
+            let program = statements(src);
+            let printed = print(&program);
+
+            assert_eq!(statements(&printed), program);
+            assert_eq!(print(&statements(&printed)), printed);
+        }
+    }
+}
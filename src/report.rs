@@ -0,0 +1,32 @@
+use crate::{
+    ast::{BlockId, Move},
+    cost::MoveKind,
+    image::Image,
+    interpreter::Block,
+    token::Position,
+};
+
+use std::collections::HashMap;
+
+/// Everything a run of a program did, returned by `Interpreter::interpret`.
+#[derive(Debug, Clone)]
+pub struct RunReport {
+    pub moves: Vec<MoveRecord>,
+    pub total_cost: u64,
+    /// The canvas after the last move.
+    pub canvas: Image,
+    /// The blocks left after the last move.
+    pub blocks: HashMap<BlockId, Block>,
+}
+
+/// How a single move was charged.
+#[derive(Debug, Clone)]
+pub struct MoveRecord {
+    pub move_: Move,
+    pub kind: MoveKind,
+    pub base_cost: u64,
+    /// The size of the block the move was charged for.
+    pub block_size: u64,
+    pub cost: u64,
+    pub position: Position,
+}
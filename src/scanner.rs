@@ -2,23 +2,28 @@ use std::rc::Rc;
 
 use crate::token::{Position, Token, TokenType};
 
+use miette::{Diagnostic, LabeledSpan, SourceCode};
+use thiserror::Error;
+
 #[derive(Debug)]
 pub struct Scanner {
-    source: Rc<Vec<u8>>,
+    source: Rc<String>,
     start: usize,
     current: usize,
     line: usize,
     col: (usize, usize),
+    errors: Vec<ScannerError>,
 }
 
 impl Scanner {
-    fn new(source: &[u8]) -> Scanner {
+    fn new(source: &str) -> Scanner {
         Scanner {
-            source: Rc::new(source.to_vec()),
+            source: Rc::new(source.to_string()),
             start: 0,
             current: 0,
             line: 0,
             col: (0, 0),
+            errors: Vec::new(),
         }
     }
 
@@ -39,7 +44,7 @@ impl Scanner {
     /// Returns the next character.
     fn peek(&self) -> Option<u8> {
         if !self.is_at_end() {
-            Some(self.source[self.current])
+            Some(self.source.as_bytes()[self.current])
         } else {
             None
         }
@@ -47,7 +52,7 @@ impl Scanner {
 
     fn advance(&mut self) -> Option<u8> {
         if !self.is_at_end() {
-            let chary = Some(self.source[self.current]);
+            let chary = Some(self.source.as_bytes()[self.current]);
             self.increment_current();
 
             chary
@@ -66,7 +71,7 @@ impl Scanner {
     }
 
     fn get_curr_lexeme(&mut self) -> String {
-        self.source[self.start..self.current]
+        self.source.as_bytes()[self.start..self.current]
             .iter()
             .cloned()
             .map(|c| c as char)
@@ -87,19 +92,24 @@ impl Scanner {
         self.mk_token_with_num(token_type, None)
     }
 
-    pub fn scan_tokens(&mut self) -> Vec<Token> {
+    /// Scans the whole source, returning every lexical error if there are any.
+    pub fn scan_tokens(&mut self) -> ScannerResult<Vec<Token>> {
         let mut tokens = Vec::new();
 
         while !self.is_at_end() {
             self.start = self.current;
             self.col = (self.col.1, self.col.1);
-            match self.scan_token() {
-                Some(token) => tokens.push(token),
-                None => (),
-            };
+            if let Some(token) = self.scan_token() {
+                tokens.push(token)
+            }
         }
         tokens.push(self.mk_token(TokenType::Eof).unwrap());
-        tokens
+
+        if self.errors.is_empty() {
+            Ok(tokens)
+        } else {
+            Err(std::mem::take(&mut self.errors))
+        }
     }
 
     fn scan_token(&mut self) -> Option<Token> {
@@ -120,7 +130,19 @@ impl Scanner {
                 self.increment_line();
                 tok
             }
-            _ => None,
+            b' ' | b'\t' | b'\r' => None,
+            _ => {
+                // Take the whole character so the error points at all of it.
+                let chary = self.source[self.start..].chars().next().unwrap();
+                for _ in 1..chary.len_utf8() {
+                    self.increment_current();
+                }
+
+                let position = self.get_curr_position();
+                self.errors
+                    .push(ScannerError::UnexpectedCharacter(chary, position));
+                None
+            }
         }
     }
 
@@ -129,12 +151,16 @@ impl Scanner {
             self.advance();
         }
 
-        let num = self
-            .get_curr_lexeme()
-            .parse::<u64>()
-            .expect("Error parsing number into u64");
-
-        self.mk_token_with_num(TokenType::Number, Some(num))
+        let lexeme = self.get_curr_lexeme();
+        match lexeme.parse::<u64>() {
+            Ok(num) => self.mk_token_with_num(TokenType::Number, Some(num)),
+            Err(_) => {
+                let position = self.get_curr_position();
+                self.errors
+                    .push(ScannerError::NumberTooBig(lexeme, position));
+                None
+            }
+        }
     }
 
     fn scan_ident(&mut self) -> Option<Token> {
@@ -147,28 +173,28 @@ impl Scanner {
             "color" => TokenType::Color,
             "swap" => TokenType::Swap,
             "merge" => TokenType::Merge,
-            // ! Change to Scanner Result
-            lex => panic!("Unexpected identifier: {lex}"),
+            lex => {
+                let error =
+                    ScannerError::UnknownIdentifier(lex.to_string(), self.get_curr_position());
+                self.errors.push(error);
+                return None;
+            }
         };
 
         self.mk_token(token_type)
     }
 
+    /// Scans a comment up to, but not including, the end of its line.
     fn scan_comment(&mut self) -> Option<Token> {
         while !self.is_at_end() && self.peek().unwrap() != b'\n' {
             self.advance();
-
-            if let Some(b'\n') = self.peek() {
-                self.advance();
-                break;
-            }
         }
 
-        None
+        self.mk_token(TokenType::Comment)
     }
 
-    pub fn scan_str(src: &str) -> Vec<Token> {
-        let mut scanny = Scanner::new(src.as_bytes());
+    pub fn scan_str(src: &str) -> ScannerResult<Vec<Token>> {
+        let mut scanny = Scanner::new(src);
         scanny.scan_tokens()
     }
 }
@@ -177,13 +203,57 @@ fn is_valid_ident(chary: u8) -> bool {
     chary.is_ascii_alphabetic() || chary == b'_'
 }
 
+#[derive(Debug, Error)]
+pub enum ScannerError {
+    #[error("Unknown word '{0}', {1}.")]
+    UnknownIdentifier(String, Position),
+    #[error("{0} is too big to be a number, {1}.")]
+    NumberTooBig(String, Position),
+    #[error("Unexpected character {0:?}, {1}.")]
+    UnexpectedCharacter(char, Position),
+}
+
+impl ScannerError {
+    pub fn position(&self) -> &Position {
+        match self {
+            ScannerError::UnknownIdentifier(_, position)
+            | ScannerError::NumberTooBig(_, position)
+            | ScannerError::UnexpectedCharacter(_, position) => position,
+        }
+    }
+}
+
+impl Diagnostic for ScannerError {
+    fn code<'a>(&'a self) -> Option<Box<dyn std::fmt::Display + 'a>> {
+        Some(Box::new("isl::scanner"))
+    }
+
+    fn source_code(&self) -> Option<&dyn SourceCode> {
+        Some(self.position().src.as_ref())
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = LabeledSpan> + '_>> {
+        let label = match self {
+            ScannerError::UnknownIdentifier(..) => "expected 'cut', 'color', 'swap' or 'merge'",
+            ScannerError::NumberTooBig(..) => "doesn't fit in 64 bits",
+            ScannerError::UnexpectedCharacter(..) => "isn't part of ISL",
+        };
+
+        let span = LabeledSpan::new_with_span(Some(label.to_string()), self.position().span());
+        Some(Box::new(std::iter::once(span)))
+    }
+}
+
+/// Every lexical error found in the source, in order.
+pub type ScannerResult<T> = Result<T, Vec<ScannerError>>;
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn scans_symbols() {
-        let tokens = Scanner::scan_str("x X y Y [ ] , . \n 69 cut color swap merge");
+        let tokens = Scanner::scan_str("x X y Y [ ] , . \n 69 cut color swap merge").unwrap();
 
         let expected_tokens = vec![
             TokenType::Vertical,
@@ -218,4 +288,28 @@ mod tests {
                 assert_eq!(expected, token, "Expected {:?}, got {:?}", expected, token)
             });
     }
+
+    #[test]
+    fn collects_every_lexical_error() {
+        let errors =
+            Scanner::scan_str("colour [0] [1, 2, 3, 4]\ncut [99999999999999999999] [x] [1] @")
+                .unwrap_err();
+
+        assert_eq!(errors.len(), 3);
+        assert!(matches!(&errors[0], ScannerError::UnknownIdentifier(word, _) if word == "colour"));
+        assert!(matches!(&errors[1], ScannerError::NumberTooBig(..)));
+        assert!(matches!(
+            errors[2],
+            ScannerError::UnexpectedCharacter('@', _)
+        ));
+        assert_eq!(errors[2].position().line, 1);
+    }
+
+    #[test]
+    fn comments_count_as_lines() {
+        let tokens = Scanner::scan_str("# a comment\ncut [0] [x] [1]").unwrap();
+        assert_eq!(tokens[0].token_type, TokenType::Comment);
+        assert_eq!(tokens[0].position.indices, (0, 11));
+        assert_eq!(tokens[2].position.line, 1);
+    }
 }
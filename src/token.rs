@@ -1,3 +1,6 @@
+use crate::ast::BlockId;
+
+use miette::SourceSpan;
 use std::rc::Rc;
 
 #[derive(Debug, Clone)]
@@ -18,6 +21,8 @@ pub enum TokenType {
     Vertical,
     Horizontal,
     HashTag,
+    /// A `#` and the rest of its line.
+    Comment,
     LeftBrace,
     RightBrace,
     NewLine,
@@ -26,12 +31,39 @@ pub enum TokenType {
     Eof,
 }
 
+impl std::fmt::Display for TokenType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use TokenType::*;
+        let name = match self {
+            Number => "a number",
+            Cut => "'cut'",
+            Color => "'color'",
+            Swap => "'swap'",
+            Merge => "'merge'",
+            Vertical => "'x'",
+            Horizontal => "'y'",
+            HashTag => "'#'",
+            Comment => "a comment",
+            LeftBrace => "'['",
+            RightBrace => "']'",
+            NewLine => "a newline",
+            Comma => "','",
+            Dot => "'.'",
+            Eof => "the end of the file",
+        };
+        write!(f, "{name}")
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Position {
     pub line: usize,
     pub col: (usize, usize),
-    pub src: Rc<Vec<u8>>,
+    pub src: Rc<String>,
     pub indices: (usize, usize),
+    /// For a move's name, the blocks the move refers to and where each is
+    /// written, filled in by the parser.
+    pub blocks: Vec<(BlockId, SourceSpan)>,
 }
 
 impl std::fmt::Display for Position {
@@ -39,7 +71,9 @@ impl std::fmt::Display for Position {
         write!(
             f,
             "on line {} column {}-{}",
-            self.line, self.col.0, self.col.1
+            self.line + 1,
+            self.col.0 + 1,
+            self.col.1
         )
     }
 }
@@ -48,7 +82,7 @@ impl Position {
     pub fn new(
         line: usize,
         col: (usize, usize),
-        src: &Rc<Vec<u8>>,
+        src: &Rc<String>,
         indices: (usize, usize),
     ) -> Position {
         Position {
@@ -56,6 +90,16 @@ impl Position {
             col,
             src: src.clone(),
             indices,
+            blocks: Vec::new(),
         }
     }
+
+    /// The byte range of the token within `src`.
+    pub fn span(&self) -> SourceSpan {
+        (
+            self.indices.0,
+            self.indices.1.saturating_sub(self.indices.0),
+        )
+            .into()
+    }
 }
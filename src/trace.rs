@@ -0,0 +1,212 @@
+use crate::{
+    ast::Color,
+    observer::{MoveEvent, Observer},
+};
+
+use serde::Serialize;
+use std::{io, io::Write, path::Path};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    JsonLines,
+    Csv,
+}
+
+impl TraceFormat {
+    /// Looks up a format by name, `csv` or `jsonl`.
+    pub fn from_name(name: &str) -> Option<TraceFormat> {
+        match name.to_ascii_lowercase().as_str() {
+            "csv" => Some(TraceFormat::Csv),
+            "jsonl" => Some(TraceFormat::JsonLines),
+            _ => None,
+        }
+    }
+
+    /// Picks the format from a `.csv` or `.jsonl` extension, `None` for any other.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<TraceFormat> {
+        TraceFormat::from_name(path.as_ref().extension()?.to_str()?)
+    }
+}
+
+/// One executed move, as written to the trace.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TraceRecord {
+    /// The 1-based source line of the move.
+    pub line: usize,
+    #[serde(rename = "move")]
+    pub move_: String,
+    pub base_cost: u64,
+    pub block_area: u64,
+    pub cost: u64,
+    /// The ids of the blocks the move left behind.
+    pub produced: Vec<String>,
+    /// An FNV-1a hash of the canvas after the move.
+    pub canvas_hash: String,
+}
+
+const CSV_HEADER: &str = "line,move,base_cost,block_area,cost,produced,canvas_hash";
+
+impl TraceRecord {
+    pub fn from_event(event: &MoveEvent) -> TraceRecord {
+        TraceRecord {
+            line: event.record.position.line + 1,
+            move_: format!("{}", event.record.move_),
+            base_cost: event.record.base_cost,
+            block_area: event.record.block_size,
+            cost: event.record.cost,
+            produced: event
+                .effect
+                .produced
+                .iter()
+                .map(|(block_id, _)| block_id.path_string())
+                .collect(),
+            canvas_hash: format!("{:016x}", canvas_hash(event.canvas)),
+        }
+    }
+
+    fn to_csv(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{}",
+            self.line,
+            csv_field(&self.move_),
+            self.base_cost,
+            self.block_area,
+            self.cost,
+            csv_field(&self.produced.join(" ")),
+            self.canvas_hash
+        )
+    }
+}
+
+/// An observer writing a trace record for every executed move.
+pub struct TraceWriter<W: Write> {
+    out: W,
+    format: TraceFormat,
+    wrote_header: bool,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(out: W, format: TraceFormat) -> TraceWriter<W> {
+        TraceWriter {
+            out,
+            format,
+            wrote_header: false,
+            error: None,
+        }
+    }
+
+    /// Flushes the trace, reporting the first error hit while writing it.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+
+        self.out.flush()
+    }
+
+    fn write_record(&mut self, record: &TraceRecord) -> io::Result<()> {
+        match self.format {
+            TraceFormat::JsonLines => {
+                serde_json::to_writer(&mut self.out, record)?;
+                writeln!(self.out)
+            }
+            TraceFormat::Csv => {
+                if !self.wrote_header {
+                    writeln!(self.out, "{CSV_HEADER}")?;
+                    self.wrote_header = true;
+                }
+                writeln!(self.out, "{}", record.to_csv())
+            }
+        }
+    }
+}
+
+impl<W: Write> Observer for TraceWriter<W> {
+    fn after_move(&mut self, event: &MoveEvent) {
+        if self.error.is_some() {
+            return;
+        }
+
+        if let Err(e) = self.write_record(&TraceRecord::from_event(event)) {
+            self.error = Some(e);
+        }
+    }
+}
+
+/// Hashes the canvas with 64-bit FNV-1a, so equal canvases hash equally across runs.
+pub fn canvas_hash(canvas: &[Color]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+
+    canvas
+        .iter()
+        .flat_map(|Color(r, g, b, a)| [*r, *g, *b, *a])
+        .fold(OFFSET_BASIS, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(PRIME)
+        })
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::tests::program;
+    use std::{cell::RefCell, rc::Rc};
+
+    fn trace(format: TraceFormat) -> String {
+        let (mut interpreter, moves) = program("cut [0] [x] [50]\ncolor [0.1] [0, 0, 0, 255]");
+        let writer = Rc::new(RefCell::new(TraceWriter::new(Vec::new(), format)));
+
+        interpreter.add_observer(Box::new(writer.clone()));
+        interpreter.interpret(&moves).unwrap();
+        writer.borrow_mut().finish().unwrap();
+
+        let out = writer.borrow().out.clone();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn writes_one_json_line_per_move() {
+        let out = trace(TraceFormat::JsonLines);
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+
+        let cut: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(cut["line"], 1);
+        assert_eq!(cut["produced"], serde_json::json!(["0.0", "0.1"]));
+        assert_eq!(cut["cost"], 7);
+
+        let color: serde_json::Value = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(color["block_area"], 5000);
+        assert_ne!(cut["canvas_hash"], color["canvas_hash"]);
+    }
+
+    #[test]
+    fn picks_formats_by_extension_or_name() {
+        assert_eq!(TraceFormat::from_path("out.CSV"), Some(TraceFormat::Csv));
+        assert_eq!(
+            TraceFormat::from_path("out.jsonl"),
+            Some(TraceFormat::JsonLines)
+        );
+        assert_eq!(TraceFormat::from_path("out.txt"), None);
+        assert_eq!(TraceFormat::from_path("out"), None);
+        assert_eq!(TraceFormat::from_name("csv"), Some(TraceFormat::Csv));
+    }
+
+    #[test]
+    fn writes_csv_with_a_header() {
+        let out = trace(TraceFormat::Csv);
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], CSV_HEADER);
+        assert!(lines[1].starts_with("1,cut [0] [X] [50],7,10000,7,0.0 0.1,"));
+        assert!(lines[2].starts_with("2,\"color [0.1] [0,0,0,255]\",5,5000,10,0.1,"));
+    }
+}